@@ -1,8 +1,11 @@
+use std::env;
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::str;
 
 use structopt::StructOpt;
 
-use super::VarsFormat;
+use super::{Documents, EscapeMode, VarsFormat};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -20,6 +23,166 @@ pub struct Opt {
     #[structopt(long = "--vars-format")]
     pub vars_format: Option<VarsFormat>,
 
+    /// which documents of a multi-document yaml vars file are used: an index counting from 0,
+    /// `merge` to merge their maps in order or `list` for an array of all of them
+    #[structopt(long = "--vars-documents")]
+    pub vars_documents: Option<Documents>,
+
+    /// shell command whose output is merged into the vars, maps key by key, can be repeated, later ones win
+    #[structopt(long = "--vars-exec", number_of_values = 1)]
+    pub vars_exec: Vec<String>,
+
+    /// format of the --vars-exec output, json, yaml or toml, guessed from the output by default
+    #[structopt(long = "--vars-exec-format")]
+    pub vars_exec_format: Option<VarsFormat>,
+
+    /// seconds a --vars-exec command may run
+    #[structopt(long = "--vars-exec-timeout", default_value = "30")]
+    pub vars_exec_timeout: u64,
+
     #[structopt(short = "o", long = "--output-file", parse(from_os_str))]
     pub output_file: Option<PathBuf>,
-}
\ No newline at end of file
+
+    /// escape substituted values for the inside of a json or yaml string, as a quoted shell word, for
+    /// xml, html or url, "auto" infers it from the output or input file extension
+    #[structopt(long = "--escape")]
+    pub escape: Option<EscapeMode>,
+
+    /// write the output while translating instead of only once the whole template succeeded,
+    /// uses less memory but leaves partial output behind on errors
+    #[structopt(long = "--stream")]
+    pub stream: bool,
+
+    /// replace the output file by renaming a temporary file next to it, so that readers never see
+    /// a partly written file, needs a writable directory
+    #[structopt(long = "--atomic", conflicts_with = "stream")]
+    pub atomic: bool,
+
+    /// parse the input as a json or yaml document and translate expressions inside its strings,
+    /// a string that is a single expression is replaced by the typed value
+    #[structopt(long = "--structured")]
+    pub structured: Option<VarsFormat>,
+
+    /// opening and closing sequence of expressions, e.g. `--delimiters '[[' ']]'`,
+    /// a template may choose its own with a `kay:delimiters [[ ]]` comment on its first line
+    #[structopt(long = "--delimiters", number_of_values = 2)]
+    pub delimiters: Vec<String>,
+
+    /// character escaping the opening delimiter, defaults to `\`
+    #[structopt(long = "--escape-char")]
+    pub escape_char: Option<char>,
+
+    /// keep the escaping of earlier versions: `\\` writes two backslashes, other escapes and a `$` not
+    /// starting an expression are dropped together with the following character
+    #[structopt(long = "--legacy-escapes")]
+    pub legacy_escapes: bool,
+
+    /// remove the indentation before and the newline after block tags such as `${define ...}`,
+    /// `${end}` or `${let ...}` so that a line holding only a block tag disappears
+    #[structopt(long = "--trim-blocks")]
+    pub trim_blocks: bool,
+
+    /// substitute `$VAR` and `${VAR}` with environment variables like GNU envsubst
+    /// instead of translating expressions, variables that are not set become empty
+    #[structopt(long = "--envsubst", conflicts_with = "structured")]
+    pub envsubst: bool,
+
+    /// render SRC to DEST instead of the input to the output file, can be repeated, a command given
+    /// after `--` replaces kay once every template rendered, e.g. `--render nginx.conf.tmpl:/etc/nginx/nginx.conf
+    /// -- nginx -g 'daemon off;'`
+    #[structopt(
+        long = "--render",
+        number_of_values = 1,
+        raw(conflicts_with_all = r#"&["input-file", "output-file"]"#)
+    )]
+    pub render: Vec<RenderTarget>,
+
+    /// pattern of the environment variables templates may read, e.g. `--env-allow 'APP_*'`,
+    /// can be repeated, every variable is readable when omitted
+    #[structopt(long = "--env-allow", number_of_values = 1)]
+    pub env_allow: Vec<String>,
+
+    /// pattern of environment variables templates may not read, even when allowed, can be repeated
+    #[structopt(long = "--env-deny", number_of_values = 1)]
+    pub env_deny: Vec<String>,
+
+    /// templates may not read any environment variable
+    #[structopt(long = "--no-env", raw(conflicts_with_all = r#"&["env-allow", "env-deny"]"#))]
+    pub no_env: bool,
+
+    /// add the environment variables templates may read to the vars as a map at `$.KEY`,
+    /// e.g. `--env-vars env` makes `${var $.env.HOME}` work
+    #[structopt(long = "--env-vars")]
+    pub env_vars: Option<String>,
+
+    /// with --env-vars, numbers and `true` / `false` become typed values instead of strings
+    #[structopt(long = "--env-coerce", requires = "env-vars")]
+    pub env_coerce: bool,
+
+    /// with --env-vars, pattern of the variables split on `:` into lists, e.g. `--env-split '*PATH'`, can be repeated
+    #[structopt(long = "--env-split", number_of_values = 1, requires = "env-vars")]
+    pub env_split: Vec<String>,
+
+    /// env names and vars selectors whose values are masked as `****` in diagnostics and diffs,
+    /// patterns matched regardless of case, e.g. `--secret '*PASSWORD*'`, can be repeated
+    #[structopt(long = "--secret", number_of_values = 1)]
+    pub secret: Vec<String>,
+
+    /// print a unified diff of the rendered output against the current output file instead of writing it,
+    /// exits with 0 when nothing would change, 1 when something would and 2 on errors
+    #[structopt(long = "--diff", raw(conflicts_with_all = r#"&["watch", "check"]"#))]
+    pub diff: bool,
+
+    /// like --diff, but only print the output files that would change
+    #[structopt(long = "--check", conflicts_with = "watch")]
+    pub check: bool,
+
+    /// render again whenever the input, the vars or a file read by the templates changes
+    #[structopt(long = "--watch")]
+    pub watch: bool,
+
+    /// with --watch, shell command executed after every successful render
+    #[structopt(long = "--watch-hook", requires = "watch")]
+    pub watch_hook: Option<String>,
+
+    /// with --envsubst, only substitute the variables referenced here, e.g. '$HOST ${PORT}'
+    #[structopt(name = "SHELL-FORMAT", requires = "envsubst")]
+    pub shell_format: Option<String>,
+
+    /// directory `${file ...}` paths are resolved from, defaults to the directory of the input file
+    #[structopt(long = "--file-base-dir", parse(from_os_str))]
+    pub file_base_dir: Option<PathBuf>,
+
+    /// additional directory `${file ...}` may read from, can be repeated
+    #[structopt(long = "--file-allow", number_of_values = 1, parse(from_os_str))]
+    pub file_allow: Vec<PathBuf>,
+}
+/// parses the arguments before `--`, the ones after it are the command to execute
+pub fn from_args() -> (Opt, Vec<OsString>) {
+    let mut args = env::args_os().collect::<Vec<_>>();
+    let command = match args.iter().position(|arg| arg == "--") {
+        Some(i) => args.split_off(i).into_iter().skip(1).collect(),
+        None => Vec::new(),
+    };
+    (Opt::from_iter(args), command)
+}
+
+/// `SRC:DEST` of `--render`
+#[derive(Debug)]
+pub struct RenderTarget {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+}
+
+impl str::FromStr for RenderTarget {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find(':') {
+            Some(i) if i > 0 && i + 1 < s.len() => Ok(RenderTarget {
+                src: s[..i].into(),
+                dest: s[i + 1..].into(),
+            }),
+            _ => Err(format!(r#"expected SRC:DEST, got "{}""#, s)),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::{self, Write};
+use std::iter;
+
+use super::parse::MASK;
+
+/// unchanged lines shown around every change
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// edits the shortest edit script may take, the trace to walk it back holds about `MAX_EDITS²` diagonals
+const MAX_EDITS: isize = 1000;
+
+/// the edit script turning `old` into `new`: the shortest one between their common first and last lines,
+/// or, past `MAX_EDITS` edits, one replacing every line in between
+fn edits<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    let mut script = vec![Edit::Keep; prefix];
+    match shortest_edits(old, new) {
+        Some(edits) => script.extend(edits),
+        None => {
+            script.extend(iter::repeat_n(Edit::Delete, old.len()));
+            script.extend(iter::repeat_n(Edit::Insert, new.len()));
+        }
+    }
+    script.extend(iter::repeat_n(Edit::Keep, suffix));
+    script
+}
+
+/// the shortest edit script turning `old` into `new` using myers' algorithm, `None` past `MAX_EDITS` edits
+fn shortest_edits<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<Edit>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // furthest x reached on every diagonal k = x - y, indexed by k + max + 1
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + max + 1) as usize;
+    // the diagonals -d..=d before every round d, to walk the path back
+    let mut trace = Vec::new();
+    'rounds: for d in 0..=max {
+        if d > MAX_EDITS {
+            return None;
+        }
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'rounds;
+            }
+        }
+    }
+
+    let mut script = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + d) as usize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) { k + 1 } else { k - 1 };
+        let prev_x = if d == 0 { 0 } else { v[at(prev_k)] };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            script.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            script.push(if x == prev_x { Edit::Insert } else { Edit::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    script.reverse();
+    Some(script)
+}
+
+/// `start,len` of a hunk header, `start` is the line before an empty range
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+struct Colors {
+    header: &'static str,
+    hunk: &'static str,
+    delete: &'static str,
+    insert: &'static str,
+    reset: &'static str,
+}
+
+const COLORS: Colors = Colors {
+    header: "\x1b[1m",
+    hunk: "\x1b[36m",
+    delete: "\x1b[31m",
+    insert: "\x1b[32m",
+    reset: "\x1b[0m",
+};
+
+const NO_COLORS: Colors = Colors {
+    header: "",
+    hunk: "",
+    delete: "",
+    insert: "",
+    reset: "",
+};
+
+/// `old` with the lines replaced by lines of `new` holding a secret masked, `secrets` are the byte ranges
+/// of the secrets in `new`: a replaced line keeps the text it shares with its replacement before and
+/// after the secret, the other lines of the change are masked completely
+pub fn mask_replaced(old: &[u8], new: &[u8], secrets: &[(usize, usize)]) -> Vec<u8> {
+    let old_lines = old.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let script = edits(&old_lines, &new_lines);
+    let mut masked = Vec::with_capacity(old.len());
+    let (mut i, mut j, mut offset) = (0, 0, 0);
+    let mut k = 0;
+    while k < script.len() {
+        if script[k] == Edit::Keep {
+            masked.extend_from_slice(old_lines[i]);
+            offset += new_lines[j].len();
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+        // a run of changes, its deleted lines are paired with its inserted lines in order
+        let end = script[k..].iter().position(|&edit| edit == Edit::Keep).map_or(script.len(), |n| k + n);
+        let deleted = script[k..end].iter().filter(|&&edit| edit == Edit::Delete).count();
+        let inserted = &new_lines[j..j + (end - k - deleted)];
+        let mut spans = Vec::new();
+        for line in inserted {
+            // the part of the line covered by secrets, relative to the line
+            let span = secrets
+                .iter()
+                .filter(|&&(start, end)| start < offset + line.len() && end > offset)
+                .fold(None, |span: Option<(usize, usize)>, &(start, end)| {
+                    let (start, end) = (start.max(offset) - offset, end.min(offset + line.len()) - offset);
+                    Some(span.map_or((start, end), |(s, e)| (s.min(start), e.max(end))))
+                });
+            spans.push(span);
+            offset += line.len();
+        }
+        let any_secret = spans.iter().any(Option::is_some);
+        for (n, line) in old_lines[i..i + deleted].iter().enumerate() {
+            match (inserted.get(n), spans.get(n).copied().flatten()) {
+                (Some(new_line), Some((start, end))) => masked.extend(mask_line(line, new_line, start, end)),
+                _ if any_secret => masked.extend(mask_line(line, b"", 0, 0)),
+                _ => masked.extend_from_slice(line),
+            }
+        }
+        i += deleted;
+        j += inserted.len();
+        k = end;
+    }
+    masked
+}
+
+/// masks `line` except for the text it shares with `new` before `start` and after `end`
+fn mask_line(line: &[u8], new: &[u8], start: usize, end: usize) -> Vec<u8> {
+    let (body, newline) = match line.strip_suffix(b"\n") {
+        Some(body) => (body, &b"\n"[..]),
+        None => (line, &b""[..]),
+    };
+    let new = new.strip_suffix(b"\n").unwrap_or(new);
+    let (start, end) = (start.min(new.len()), end.min(new.len()));
+    let prefix = body.iter().zip(&new[..start]).take_while(|(a, b)| a == b).count();
+    let suffix = body[prefix..]
+        .iter()
+        .rev()
+        .zip(new[end..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut masked = body[..prefix].to_vec();
+    masked.extend_from_slice(MASK);
+    masked.extend_from_slice(&body[body.len() - suffix..]);
+    masked.extend_from_slice(newline);
+    masked
+}
+
+/// writes the unified diff of `old` and `new`, returns whether they differ
+pub fn write_diff<W: Write>(
+    output: &mut W,
+    old_name: &str,
+    old: &[u8],
+    new_name: &str,
+    new: &[u8],
+    color: bool,
+) -> io::Result<bool> {
+    let old_lines = old.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    // every edit with the index of its line in the old and the new input
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edits(&old_lines, &new_lines) {
+        ops.push((edit, i, j));
+        match edit {
+            Edit::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (edit, _, _))| *edit != Edit::Keep)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return Ok(false);
+    }
+
+    let colors = if color { COLORS } else { NO_COLORS };
+    writeln!(output, "{}--- {}{}", colors.header, old_name, colors.reset)?;
+    writeln!(output, "{}+++ {}{}", colors.header, new_name, colors.reset)?;
+    let mut first = 0;
+    while first < changes.len() {
+        // changes closer than twice the context share a hunk
+        let mut last = first;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+        let hunk = &ops[changes[first].saturating_sub(CONTEXT)..(changes[last] + CONTEXT + 1).min(ops.len())];
+        let (_, old_start, new_start) = hunk[0];
+        let old_len = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Insert).count();
+        let new_len = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Delete).count();
+        writeln!(
+            output,
+            "{}@@ -{} +{} @@{}",
+            colors.hunk,
+            range(old_start, old_len),
+            range(new_start, new_len),
+            colors.reset
+        )?;
+        for &(edit, i, j) in hunk {
+            let (prefix, line, color) = match edit {
+                Edit::Keep => (' ', old_lines[i], ""),
+                Edit::Delete => ('-', old_lines[i], colors.delete),
+                Edit::Insert => ('+', new_lines[j], colors.insert),
+            };
+            let text = line.strip_suffix(b"\n").unwrap_or(line);
+            write!(output, "{}{}", color, prefix)?;
+            output.write_all(text)?;
+            writeln!(output, "{}", if color.is_empty() { "" } else { colors.reset })?;
+            if text.len() == line.len() {
+                writeln!(output, "\\ No newline at end of file")?;
+            }
+        }
+        first = last + 1;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edits, mask_replaced, write_diff, Edit};
+
+    fn diff(old: &str, new: &str) -> Option<String> {
+        let mut output = Vec::new();
+        let changed = write_diff(&mut output, "a", old.as_bytes(), "b", new.as_bytes(), false).unwrap();
+        assert_eq!(changed, !output.is_empty());
+        if changed {
+            Some(String::from_utf8(output).unwrap())
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn shortest_edit_script() {
+        let (old, new) = ("ABCABBA".as_bytes(), "CBABAC".as_bytes());
+        let script = edits(old, new);
+        // the example of myers' paper has 5 deletions and insertions
+        assert_eq!(script.iter().filter(|&&edit| edit != Edit::Keep).count(), 5);
+        assert_eq!(script.iter().filter(|&&edit| edit != Edit::Insert).count(), old.len());
+        assert_eq!(script.iter().filter(|&&edit| edit != Edit::Delete).count(), new.len());
+        assert!(edits::<u8>(&[], &[]).is_empty());
+        assert_eq!(edits(b"", b"ab"), [Edit::Insert, Edit::Insert]);
+        assert_eq!(edits(b"ab", b""), [Edit::Delete, Edit::Delete]);
+    }
+
+    #[test]
+    fn many_edits_replace_the_changed_lines() {
+        let old = (0..3000).map(|i| i % 7).collect::<Vec<_>>();
+        let mut new = (0..3000).map(|i| i % 5 + 10).collect::<Vec<_>>();
+        new[0] = 0;
+        new[2999] = old[2999];
+        let script = edits(&old, &new);
+        assert_eq!(script.len(), 2 + 2 * 2998);
+        assert_eq!((script[0], script[script.len() - 1]), (Edit::Keep, Edit::Keep));
+        assert!(script[1..=2998].iter().all(|&edit| edit == Edit::Delete));
+        assert!(script[2999..2 * 2999 - 1].iter().all(|&edit| edit == Edit::Insert));
+        // within the bound the script stays the shortest one
+        let mut new = old.clone();
+        new.splice(1000..1400, 100..400);
+        let script = edits(&old, &new);
+        assert_eq!(script.iter().filter(|&&edit| edit != Edit::Keep).count(), 700);
+    }
+
+    #[test]
+    fn identical() {
+        assert_eq!(diff("", ""), None);
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+    }
+
+    #[test]
+    fn hunks() {
+        assert_eq!(diff("a\nb\nc\n", "a\nB\nc\n").unwrap(), "--- a\n+++ b\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        assert_eq!(diff("", "new\n").unwrap(), "--- a\n+++ b\n@@ -0,0 +1 @@\n+new\n");
+        assert_eq!(diff("old\n", "").unwrap(), "--- a\n+++ b\n@@ -1 +0,0 @@\n-old\n");
+        let old = (1..=20).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = (1..=20)
+            .map(|i| match i {
+                2 => String::from("two\n"),
+                19 => String::from("nineteen\n"),
+                i => format!("{}\n", i),
+            })
+            .collect::<String>();
+        assert_eq!(
+            diff(&old, &new).unwrap(),
+            "--- a\n+++ b\n@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n@@ -16,5 +16,5 @@\n 16\n 17\n 18\n-19\n+nineteen\n 20\n"
+        );
+    }
+
+    #[test]
+    fn missing_newline() {
+        assert_eq!(
+            diff("a\nb", "a\nb\n").unwrap(),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+
+    #[test]
+    fn replaced_secrets_are_masked() {
+        let mask = |old: &str, new: &str, secret: &str| {
+            let start = new.find(secret).unwrap();
+            String::from_utf8(mask_replaced(old.as_bytes(), new.as_bytes(), &[(start, start + secret.len())])).unwrap()
+        };
+        assert_eq!(mask("a\npass: old\nb\n", "a\npass: new\nb\n", "new"), "a\npass: ****\nb\n");
+        // the shared text does not reach into the secret
+        assert_eq!(mask("pass: s3cretA\n", "pass: s3cretB\n", "s3cretB"), "pass: ****\n");
+        assert_eq!(mask("pass: \"old\"\n", "pass: \"new\"\n", "new"), "pass: \"****\"\n");
+        // every line of a change holding a secret is masked
+        assert_eq!(mask("a\nb\nc\n", "a\nkey: new\n", "new"), "a\n****\n****\n");
+        // other changes and unchanged lines are kept
+        assert_eq!(mask("x\nsecret\n", "y\nsecret\n", "secret"), "x\nsecret\n");
+        assert_eq!(mask_replaced(b"a\nb", b"c\n", &[]), b"a\nb");
+    }
+}
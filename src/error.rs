@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 use std::io;
 use std::fmt;
-use super::{InputStream, OutputStream, ExprInternalError, VarsError};
+use super::{InputStream, OutputStream, ExprInternalError, VarsError, VarsFormat};
 
 pub enum Error<'a> {
     UnknownFormat,
@@ -10,7 +10,19 @@ pub enum Error<'a> {
     Input(InputStream<'a>, io::Error),
     Output(OutputStream<'a>, io::Error),
     JsonParseVars(PathBuf, serde_json::error::Error),
-    YamlParseVars(PathBuf, serde_yaml::Error),
+    YamlParseVars(PathBuf, String),
+    TomlParseVars(PathBuf, toml::de::Error),
+    ExecVars(String, io::Error),
+    ExecVarsFailed(String, String),
+    ExecVarsParse(String, VarsFormat, String),
+    InvalidVarsMerge(String),
+    JsonParseTemplate(String, serde_json::error::Error),
+    YamlParseTemplate(String, serde_yaml::Error),
+    InvalidDelimiters(String),
+    InvalidEnvMount(String),
+    Exec(String, io::Error),
+    Watch(io::Error),
+    Usage(&'static str),
     Expr(ExprInternalError),
     Vars(Option<PathBuf>, VarsError),
 }
@@ -23,26 +35,62 @@ impl<'a> fmt::Display for Error<'a> {
                 InputStream::Stdin(_) => write!(f, r#"Error reading from stdin: {} "#, error),
             },
             Self::Output(stream, error) => match stream {
-                OutputStream::File(path, _) => write!(f, r#"Error writing to "{}": {} "#, path.display(), error),
-                OutputStream::Stdout(_) => write!(f, r#"Error writing to stdout: {} "#, error),
+                OutputStream::File(path, _)
+                | OutputStream::BufferedFile(path, _)
+                | OutputStream::AtomicFile(path, _)
+                | OutputStream::Memory(path, _) => write!(f, r#"Error writing to "{}": {} "#, path.display(), error),
+                OutputStream::Stdout(_) | OutputStream::BufferedStdout(..) => write!(f, r#"Error writing to stdout: {} "#, error),
             },
             Self::UnknownFormat => write!(f, r#"Unable to determine vars format from file extension, please use --vars-format argument"#),
             Self::JsonParseVars(path, json_error) => write!(f, r#"Unable to parse json vars file "{}": {}"#, path.display(), json_error),
             Self::YamlParseVars(path, yaml_error) => write!(f, r#"Unable to parse yaml vars file "{}": {} "#, path.display(), yaml_error),
+            Self::TomlParseVars(path, toml_error) => write!(f, r#"Unable to parse toml vars file "{}": {}"#, path.display(), toml_error),
+            Self::ExecVars(command, error) => write!(f, r#"Unable to execute --vars-exec "{}": {}"#, command, error),
+            Self::ExecVarsFailed(command, reason) => write!(f, r#"--vars-exec "{}" failed: {}"#, command, reason),
+            Self::ExecVarsParse(command, format, message) => write!(f, r#"Unable to parse the output of --vars-exec "{}" as {:?}: {}"#, command, format, message),
+            Self::InvalidVarsMerge(command) => write!(f, r#"Unable to merge the output of --vars-exec "{}" into the vars, both must be maps"#, command),
+            Self::JsonParseTemplate(name, json_error) => write!(f, r#"Unable to parse json template "{}": {}"#, name, json_error),
+            Self::YamlParseTemplate(name, yaml_error) => write!(f, r#"Unable to parse yaml template "{}": {}"#, name, yaml_error),
             Self::FileRead(path, error) => write!(f, r#"Unable to read file "{}": {} "#, path.display(), error),
             Self::FileWrite(path, error) => write!(f, r#"Unable to write file "{}": {} "#, path.display(), error),
+            Self::Watch(error) => write!(f, r#"Unable to watch files: {}"#, error),
+            Self::Usage(message) => write!(f, "{}", message),
+            Self::Exec(program, error) => write!(f, r#"Unable to execute "{}": {}"#, program, error),
+            Self::InvalidEnvMount(key) => write!(f, r#"Unable to mount the environment at "$.{}", the vars or the value there are not a map"#, key),
+            Self::InvalidDelimiters(message) => write!(f, r#"Invalid --delimiters: {}"#, message),
             Self::Expr(expr_err) => {
                 match expr_err {
                     ExprInternalError::UnexpectedEof => write!(f, r#"Unexpected end of file in "#),
                     ExprInternalError::UnknownExpressionType => write!(f, r#"Unknown expression type"#),
                     ExprInternalError::UnknownEnv(var_name) => write!(f, r#"Environment variable "{}" is not defined"#, var_name),
+                    ExprInternalError::EnvAccessDenied(var_name) => write!(f, r#"Reading environment variable "{}" is not allowed, see --env-allow, --env-deny and --no-env"#, var_name),
+                    ExprInternalError::UnknownFilter(name) => write!(f, r#"Unknown filter "{}""#, name),
+                    ExprInternalError::InvalidFilterArgument(filter) => write!(f, r#"Invalid arguments for filter "{}""#, filter),
+                    ExprInternalError::FileRead(path, error) => write!(f, r#"Unable to read file "{}": {}"#, path.display(), error),
+                    ExprInternalError::FileAccessDenied(path) => write!(f, r#"Reading file "{}" is not allowed, see --file-allow"#, path.display()),
+                    ExprInternalError::IncludeCycle(path) => write!(f, r#"Template "{}" includes itself"#, path.display()),
+                    ExprInternalError::InvalidSyntax(tag) => write!(f, r#"Invalid syntax "{}""#, tag),
+                    ExprInternalError::InvalidDelimiters(message) => write!(f, r#"Invalid delimiters comment: {}"#, message),
+                    ExprInternalError::SyntaxError(message, span) => write!(f, r#"Syntax error at "{}" (offset {}..{}): {}"#, span.text, span.start, span.end, message),
+                    ExprInternalError::InvalidOperands(op, left, right, span) => write!(f, r#"Operator "{}" cannot be applied to {} and {} in "{}" (offset {}..{})"#, op, left, right, span.text, span.start, span.end),
+                    ExprInternalError::InvalidOperand(op, operand, span) => write!(f, r#"Operator "{}" cannot be applied to {} in "{}" (offset {}..{})"#, op, operand, span.text, span.start, span.end),
+                    ExprInternalError::DivisionByZero(span) => write!(f, r#"Division by zero in "{}" (offset {}..{})"#, span.text, span.start, span.end),
+                    ExprInternalError::IntegerOverflow(span) => write!(f, r#"Integer overflow in "{}" (offset {}..{})"#, span.text, span.start, span.end),
+                    ExprInternalError::UnexpectedEnd(tag) => write!(f, r#"Unexpected "{}" without a matching block"#, tag),
+                    ExprInternalError::UnclosedBlock(tag) => write!(f, r#"Block "{}" is not closed"#, tag),
+                    ExprInternalError::UnknownName(name) => write!(f, r#"Name "{}" is not defined"#, name),
+                    ExprInternalError::UnknownMacro(name) => write!(f, r#"Macro "{}" is not defined"#, name),
+                    ExprInternalError::MacroArity(name, expected, got) => write!(f, r#"Macro "{}" expects {} arguments, got {}"#, name, expected, got),
+                    ExprInternalError::RecursiveMacro(name) => write!(f, r#"Macro "{}" calls itself"#, name),
+                    ExprInternalError::NotIterable(type_name) => write!(f, r#"Cannot loop over a {}, only over a list"#, type_name),
+                    ExprInternalError::ParameterError(name, message) if message.is_empty() => write!(f, r#"{}: parameter null or not set"#, name),
+                    ExprInternalError::ParameterError(name, message) => write!(f, r#"{}: {}"#, name, message),
                 }
             }
             Self::Vars(_, VarsError::InvalidSelector(selector, err)) => write!(f, r#"Variable selector "{}" is invalid: {}"#, selector, err),
             Self::Vars(Some(path), VarsError::NotFound(selector)) => write!(f, r#"Variable "{}" not found in "{}""#, selector, path.display()),
-            Self::Vars(Some(_path), VarsError::MultipleResults(selector)) => write!(f, r#"Multiple results match selector "{}""#, selector),
-            Self::Vars(Some(_path), VarsError::StringConv(selector)) => write!(f, r#"Unable to convert selector result to string {}"#, selector),
-            Self::Vars(None, _) => unreachable!(),
+            Self::Vars(None, VarsError::NotFound(selector)) => write!(f, r#"Variable "{}" not found, see --vars-file and --env-vars"#, selector),
+            Self::Vars(_, VarsError::MultipleResults(selector)) => write!(f, r#"Multiple results match selector "{}""#, selector),
 
         }
     }
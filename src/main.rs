@@ -1,40 +1,106 @@
-use std::io;
-use std::path::PathBuf;
-use std::process::exit;
-
-use structopt::StructOpt;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::CommandExt;
+use std::process::{exit, Command};
+use std::time::Duration;
 
 mod cli;
+mod diff;
 mod error;
 mod parse;
 mod position_iterator;
 mod read_chars;
 mod streams;
+mod value;
 mod vars;
+mod watch;
 
 use error::Error;
-use parse::{ExprError, ExprInternalError, TranslateError};
+use parse::{Context, Delimiters, EnvPolicy, Escape, EscapeMode, ExprError, ExprInternalError, FilePolicy, Scope, Secrets, TranslateError};
 use position_iterator::PositionIterator;
 use read_chars::ReadChars;
-use streams::{InputStream, OutputStream};
+use streams::{InputStream, OutputStream, WriteMode};
+pub use value::Value;
 pub use vars::Vars;
-use vars::{EmptyVars, JsonVars, VarsError, VarsFormat, YamlVars};
+use vars::{Document, Documents, EmptyVars, EnvMount, JsonVars, TagPolicy, VarsError, VarsFormat, YamlVars};
+use watch::Watcher;
+
+/// a template and where its output goes
+struct Render<'a> {
+    input: InputStream<'a>,
+    output: OutputStream<'a>,
+    template_dir: PathBuf,
+    escape: Escape,
+}
+
+/// what `--diff` / `--check` report instead of writing the output
+#[derive(Clone, Copy, PartialEq)]
+enum Compare {
+    Diff,
+    Check,
+}
+
+/// how rendering ended, several templates end with the last of these any of them reached
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Rendered,
+    Unchanged,
+    Changed,
+    Failed,
+}
 
-fn cli_args<'a>(
+/// settings shared by every rendered template
+struct Options {
+    vars: Box<dyn Vars>,
+    vars_file: Option<PathBuf>,
+    delimiters: Delimiters,
+    trim_blocks: bool,
+    structured: Option<VarsFormat>,
+    /// `Some` in envsubst mode, holding the variables of the SHELL-FORMAT argument if given
+    envsubst: Option<Option<Vec<String>>>,
+    /// name the template in every diagnostic, not only in those of included templates
+    name_templates: bool,
+    compare: Option<Compare>,
+    env: EnvPolicy,
+    secrets: Secrets,
+}
+
+struct Args<'a> {
+    /// every template with the files it may read
+    renders: Vec<(Render<'a>, FilePolicy)>,
+    options: Options,
+}
+
+fn render_args<'a>(
+    opt: &cli::Opt,
+    input_file: Option<PathBuf>,
+    output_file: Option<PathBuf>,
     stdin: &'a io::Stdin,
     stdout: &'a io::Stdout,
-) -> Result<
-    (
-        InputStream<'a>,
-        OutputStream<'a>,
-        Box<dyn Vars>,
-        Option<PathBuf>,
-    ),
-    Error<'a>,
-> {
-    let opt = cli::Opt::from_args();
-
-    let input = if let Some(input_file) = opt.input_file {
+) -> Result<(Render<'a>, FilePolicy), Error<'a>> {
+    let template_dir = input_file
+        .as_ref()
+        .and_then(|input_file| input_file.parent())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    // templates may always read from the directory `${file ...}` paths are resolved from
+    let mut allowed = vec![opt.file_base_dir.clone().unwrap_or_else(|| template_dir.clone())];
+    allowed.extend(opt.file_allow.iter().cloned());
+    let files = FilePolicy::new(opt.file_base_dir.clone(), allowed, template_dir.clone());
+
+    let escape = match opt.escape {
+        Some(EscapeMode::Syntax(escape)) => escape,
+        Some(EscapeMode::Auto) => output_file
+            .as_ref()
+            .and_then(Escape::from_extension)
+            .or_else(|| input_file.as_ref().and_then(Escape::from_extension))
+            .unwrap_or(Escape::None),
+        None => Escape::None,
+    };
+
+    let input = if let Some(input_file) = input_file {
         InputStream::from_file(input_file)?
     } else {
         if atty::is(atty::Stream::Stdin) {
@@ -43,58 +109,470 @@ fn cli_args<'a>(
         InputStream::from_stdin(stdin)
     };
 
-    let output = if let Some(output_file) = opt.output_file {
-        OutputStream::from_file(output_file)?
-    } else {
-        OutputStream::from_stdin(stdout)
+    let output = match output_file {
+        Some(output_file) if opt.diff || opt.check => OutputStream::in_memory(output_file),
+        Some(output_file) => {
+            let mode = if opt.stream {
+                WriteMode::Stream
+            } else if opt.atomic {
+                WriteMode::Atomic
+            } else {
+                WriteMode::Buffered
+            };
+            OutputStream::from_file(output_file, mode)?
+        }
+        None => OutputStream::from_stdin(stdout, opt.stream),
+    };
+    let render = Render {
+        input,
+        output,
+        template_dir,
+        escape,
+    };
+    Ok((render, files))
+}
+
+fn cli_args<'a>(opt: &cli::Opt, stdin: &'a io::Stdin, stdout: &'a io::Stdout) -> Result<Args<'a>, Error<'a>> {
+    let mut delimiters = match opt.delimiters.as_slice() {
+        [open, close] => Delimiters::new(open, close, opt.escape_char).map_err(Error::InvalidDelimiters)?,
+        _ => Delimiters::new("${", "}", opt.escape_char).map_err(Error::InvalidDelimiters)?,
     };
+    delimiters.legacy_escapes = opt.legacy_escapes;
+    let vars_from_stdin = opt.vars_file.as_ref().is_some_and(|vars_file| vars_file.as_os_str() == "-");
+    if vars_from_stdin && opt.input_file.is_none() && opt.render.is_empty() {
+        return Err(Error::Usage("--vars-file - reads the vars from stdin, the template needs --input-file or --render"));
+    }
+    if let Some(VarsFormat::Toml) = opt.structured {
+        return Err(Error::Usage("--structured supports json and yaml documents"));
+    }
 
-    let vars: Box<dyn Vars> = if let Some(vars_file) = opt.vars_file.clone() {
+    let renders = if opt.render.is_empty() {
+        vec![render_args(opt, opt.input_file.clone(), opt.output_file.clone(), stdin, stdout)?]
+    } else {
+        opt.render
+            .iter()
+            .map(|target| render_args(opt, Some(target.src.clone()), Some(target.dest.clone()), stdin, stdout))
+            .collect::<Result<_, _>>()?
+    };
+    let env = EnvPolicy::new(opt.env_allow.clone(), opt.env_deny.clone(), !opt.no_env);
+    // yaml tags may read from the vars file's directory
+    let vars_dir = opt
+        .vars_file
+        .as_deref()
+        .filter(|_| !vars_from_stdin)
+        .and_then(Path::parent)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let mut allowed = vec![vars_dir.clone()];
+    allowed.extend(opt.file_allow.iter().cloned());
+    let vars_files = FilePolicy::new(None, allowed, vars_dir);
+    let tags = TagPolicy {
+        env: &env,
+        files: &vars_files,
+    };
+    let mut vars: Option<Box<dyn Document>> = if vars_from_stdin {
+        Some(vars::from_stdin(stdin, opt.vars_format, opt.vars_documents, &tags)?)
+    } else if let Some(vars_file) = opt.vars_file.clone() {
         let format = match (opt.vars_format, VarsFormat::from_extension(&vars_file)) {
             (Some(f), _) => f,
             (None, Some(f)) => f,
             _ => Err(Error::UnknownFormat)?,
         };
         match format {
-            VarsFormat::Json => Box::new(JsonVars::from_file(vars_file)?),
-            VarsFormat::Yaml => Box::new(YamlVars::from_file(vars_file)?),
+            VarsFormat::Json => Some(Box::new(JsonVars::from_file(vars_file)?)),
+            VarsFormat::Yaml => Some(Box::new(YamlVars::from_file(vars_file, opt.vars_documents, &tags)?)),
+            VarsFormat::Toml => Some(Box::new(vars::from_toml_file(vars_file)?)),
         }
     } else {
-        Box::new(EmptyVars::new())
+        None
     };
-    Ok((input, output, vars, opt.vars_file))
+    for command in &opt.vars_exec {
+        let timeout = Duration::from_secs(opt.vars_exec_timeout);
+        let output = vars::exec_vars(command, opt.vars_exec_format, timeout, &tags)?;
+        let vars = vars.get_or_insert_with(|| Box::new(JsonVars::new()));
+        vars.json_vars().merge(output, command)?;
+    }
+    if let Some(key) = &opt.env_vars {
+        let vars = vars.get_or_insert_with(|| Box::new(JsonVars::new()));
+        vars.json_vars().mount_env(&EnvMount {
+            key,
+            policy: &env,
+            coerce: opt.env_coerce,
+            split: &opt.env_split,
+        })?;
+    }
+    let vars: Box<dyn Vars> = match vars {
+        Some(vars) => vars,
+        None => Box::new(EmptyVars::new()),
+    };
+
+    Ok(Args {
+        renders,
+        options: Options {
+            vars,
+            vars_file: opt.vars_file.clone(),
+            delimiters,
+            trim_blocks: opt.trim_blocks,
+            structured: opt.structured,
+            envsubst: if opt.envsubst {
+                Some(opt.shell_format.as_ref().map(|format| parse::shell_format_variables(format)))
+            } else {
+                None
+            },
+            name_templates: !opt.render.is_empty(),
+            compare: match (opt.diff, opt.check) {
+                (true, _) => Some(Compare::Diff),
+                (_, true) => Some(Compare::Check),
+                _ => None,
+            },
+            env,
+            secrets: Secrets::new(&opt.secret),
+        },
+    })
 }
 
-fn real_main<'a>(stdin: &'a io::Stdin, stdout: &'a io::Stdout) -> Result<(), Error<'a>> {
-    let (mut input, mut output, vars, vars_file) = cli_args(stdin, stdout)?;
+fn real_main<'a>(
+    opt: cli::Opt,
+    command: Vec<OsString>,
+    stdin: &'a io::Stdin,
+    stdout: &'a io::Stdout,
+) -> Result<(), Error<'a>> {
+    if opt.diff || opt.check {
+        if opt.output_file.is_none() && opt.render.is_empty() {
+            return Err(Error::Usage("--diff and --check compare with --output-file or the --render targets"));
+        }
+        if !command.is_empty() {
+            return Err(Error::Usage("--diff and --check do not execute a command"));
+        }
+    }
+    if opt.watch {
+        if opt.input_file.is_none() && opt.render.is_empty() {
+            return Err(Error::Usage("--watch needs the templates as files, see --input-file and --render"));
+        }
+        if !command.is_empty() {
+            return Err(Error::Usage("--watch does not execute a command, see --watch-hook"));
+        }
+        if opt.vars_file.as_ref().is_some_and(|vars_file| vars_file.as_os_str() == "-") {
+            return Err(Error::Usage("--watch cannot read the vars from stdin again"));
+        }
+        return watch(&opt, stdin, stdout);
+    }
 
-    let mut input_chars = PositionIterator::from(input.chars());
+    let Args { renders, options } = cli_args(&opt, stdin, stdout)?;
+    match (options.compare, render_all(renders, &options).0) {
+        (Some(_), Outcome::Changed) => exit(1),
+        (Some(_), Outcome::Failed) => exit(2),
+        (None, Outcome::Failed) => exit(1),
+        _ => {}
+    }
+    if let Some((program, args)) = command.split_first() {
+        // replaces this process, so that signals reach the command
+        let err = Command::new(program).args(args).exec();
+        return Err(Error::Exec(program.to_string_lossy().into_owned(), err));
+    }
+    Ok(())
+}
+
+/// renders every template, printing the diagnostics of those that fail, the outputs are committed
+/// or compared only once every template rendered, returns how they ended and the files they read
+fn render_all(renders: Vec<(Render, FilePolicy)>, options: &Options) -> (Outcome, Vec<PathBuf>) {
+    let mut outputs = Vec::new();
+    let mut failed = false;
+    let mut accessed = Vec::new();
+    for (render, files) in renders {
+        match render_template(render, &files, options) {
+            Ok(Some(output)) => outputs.push(output),
+            Ok(None) => failed = true,
+            Err(err) => {
+                eprintln!("{}", options.secrets.mask_str(&err.to_string()));
+                failed = true;
+            }
+        }
+        accessed.extend(files.accessed());
+    }
+    if failed {
+        // dropping the held back outputs leaves their targets as they are
+        return (Outcome::Failed, accessed);
+    }
+    let mut outcome = Outcome::Rendered;
+    for output in outputs {
+        match finish(output, options) {
+            Ok(finished) => outcome = outcome.max(finished),
+            Err(err) => {
+                eprintln!("{}", options.secrets.mask_str(&err.to_string()));
+                outcome = Outcome::Failed;
+            }
+        }
+    }
+    (outcome, accessed)
+}
 
-    if let Err(err) = parse::translate(&mut input_chars, &mut output, &vars) {
-        eprintln!(
-            r#"Error [line: {} col: {}] {}"#,
-            input_chars.line(),
-            input_chars.col(),
-            match err {
-                TranslateError::Input(io_err) => return Err(Error::Input(input, io_err)),
-                TranslateError::Output(io_err) => Error::Output(output, io_err),
-                TranslateError::Expr(ExprError::Output(io_err)) => Error::Output(output, io_err),
-                TranslateError::Expr(ExprError::Input(io_err)) => Error::Input(input, io_err),
-                TranslateError::Expr(ExprError::Vars(vars_err)) => Error::Vars(vars_file, vars_err),
-                TranslateError::Expr(ExprError::Internal(expr_err)) => Error::Expr(expr_err),
-                TranslateError::Vars(expr_err) => Error::Vars(vars_file, expr_err),
+/// time without further changes before rendering again
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// renders the templates again whenever one of them, the vars or a file they read changes
+fn watch<'a>(opt: &cli::Opt, stdin: &'a io::Stdin, stdout: &'a io::Stdout) -> Result<(), Error<'a>> {
+    let mut watcher = Watcher::new().map_err(Error::Watch)?;
+    let secrets = Secrets::from_env(&opt.secret);
+    loop {
+        let mut watched = opt.render.iter().map(|target| target.src.clone()).collect::<Vec<_>>();
+        watched.extend(opt.input_file.iter().chain(&opt.vars_file).cloned());
+        match cli_args(opt, stdin, stdout) {
+            Ok(Args { renders, options }) => {
+                let (outcome, accessed) = render_all(renders, &options);
+                watched.extend(accessed);
+                if let (Outcome::Rendered, Some(hook)) = (outcome, &opt.watch_hook) {
+                    match Command::new("sh").arg("-c").arg(hook).status() {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => eprintln!(r#"Hook "{}" failed: {}"#, hook, status),
+                        Err(err) => eprintln!(r#"Unable to execute hook "{}": {}"#, hook, err),
+                    }
+                }
+            }
+            Err(err) => eprintln!("{}", secrets.mask_str(&err.to_string())),
+        }
+        for (dir, err) in watcher.watch(&watched) {
+            eprintln!(r#"Unable to watch "{}": {}"#, dir.display(), err);
+        }
+        watcher.wait(DEBOUNCE).map_err(Error::Watch)?;
+    }
+}
+
+/// translates one template, returns its output to be committed, or `None` when it failed after printing the diagnostics
+fn render_template<'a>(
+    render: Render<'a>,
+    files: &FilePolicy,
+    options: &Options,
+) -> Result<Option<OutputStream<'a>>, Error<'a>> {
+    let Render {
+        mut input,
+        mut output,
+        template_dir,
+        escape,
+    } = render;
+    let (template, template_name) = match &input {
+        InputStream::File(path, _) => (path.canonicalize().ok(), path.display().to_string()),
+        InputStream::Stdin(_) => (None, String::from("<stdin>")),
+    };
+    let mut ctx = Context {
+        vars: options.vars.as_ref(),
+        files,
+        env: &options.env,
+        secrets: &options.secrets,
+        // in structured mode values are escaped by serializing the document
+        escape: if options.structured.is_some() { Escape::None } else { escape },
+        delimiters: options.delimiters.clone(),
+        trim_blocks: options.trim_blocks,
+        dir: template_dir,
+        template,
+        parent: None,
+    };
+    let vars_file = options.vars_file.clone();
+
+    if let Some(format) = &options.structured {
+        let mut template = String::new();
+        if let Err(io_err) = input.read_to_string(&mut template) {
+            return Err(Error::Input(input, io_err));
+        }
+        let document = match format {
+            VarsFormat::Json => serde_json::from_str(&template)
+                .map_err(|e| Error::JsonParseTemplate(template_name.clone(), e))?,
+            VarsFormat::Yaml => serde_yaml::from_str(&template)
+                .map_err(|e| Error::YamlParseTemplate(template_name.clone(), e))?,
+            VarsFormat::Toml => unreachable!("rejected by cli_args"),
+        };
+        if let Some(delimiters) = Delimiters::from_magic_comment(&template) {
+            let legacy_escapes = ctx.delimiters.legacy_escapes;
+            ctx.delimiters = delimiters.map_err(|e| Error::Expr(ExprInternalError::InvalidDelimiters(e)))?;
+            ctx.delimiters.legacy_escapes = legacy_escapes;
+        }
+        return match parse::translate_document(document, "$", &ctx, &mut Scope::new()) {
+            Ok(document) => match parse::write_document(&document, format, &mut output) {
+                Ok(()) => Ok(Some(output)),
+                Err(e) => Err(Error::Output(output, e)),
             },
-        );
-        exit(1);
+            Err((path, err)) => {
+                let (err, includes) = err.unwind_includes();
+                let location = format!("{} {}", template_name, path);
+                print_error(&location, &includes, translate_error(err, input, output, vars_file), &options.secrets);
+                Ok(None)
+            }
+        };
+    }
+
+    let mut input_chars = PositionIterator::from(input.chars());
+
+    let result = match &options.envsubst {
+        Some(variables) => parse::translate_envsubst(&mut input_chars, &mut output, &ctx, variables.as_deref()),
+        None => parse::translate(&mut input_chars, &mut output, &ctx, &mut Scope::new()),
+    };
+    if let Err(err) = result {
+        let (line, col) = (input_chars.line(), input_chars.col());
+        let (err, includes) = err.unwind_includes();
+        let location = if includes.is_empty() && !options.name_templates {
+            format!("line: {} col: {}", line, col)
+        } else {
+            format!("{}:{}:{}", template_name, line, col)
+        };
+        print_error(&location, &includes, translate_error(err, input, output, vars_file), &options.secrets);
+        return Ok(None);
+    }
+    Ok(Some(output))
+}
+
+/// commits the output of a template that translated, or compares it with its target
+fn finish<'a>(mut output: OutputStream<'a>, options: &Options) -> Result<Outcome, Error<'a>> {
+    let (path, rendered) = match &output {
+        OutputStream::Memory(path, rendered) => (path, rendered),
+        _ => {
+            output.commit().map_err(|e| Error::Output(output, e))?;
+            return Ok(Outcome::Rendered);
+        }
+    };
+    let current = match fs::read(path) {
+        Ok(current) => Some(current),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(Error::FileRead(path.clone(), e)),
+    };
+    let mut stdout = io::stdout().lock();
+    let changed = match options.compare {
+        Some(Compare::Diff) => {
+            let name = path.display().to_string();
+            let (old_name, current) = match &current {
+                // an old value of a line holding a secret is just as secret
+                Some(current) => (name.as_str(), diff::mask_replaced(current, rendered, &options.secrets.ranges(rendered))),
+                None => ("/dev/null", Vec::new()),
+            };
+            let color = atty::is(atty::Stream::Stdout);
+            let mut diff = Vec::new();
+            diff::write_diff(&mut diff, old_name, &current, &name, rendered, color)
+                .and_then(|changed| stdout.write_all(&options.secrets.mask(&diff)).map(|_| changed))
+        }
+        _ if current.as_ref() == Some(rendered) => Ok(false),
+        _ => writeln!(stdout, "{}", path.display()).map(|_| true),
+    };
+    match changed {
+        Ok(true) => Ok(Outcome::Changed),
+        Ok(false) => Ok(Outcome::Unchanged),
+        Err(e) => Err(Error::Output(OutputStream::Stdout(io::stdout().lock()), e)),
+    }
+}
+
+/// maps an error of the translation to the error reported to the user
+fn translate_error<'a>(
+    err: TranslateError,
+    input: InputStream<'a>,
+    output: OutputStream<'a>,
+    vars_file: Option<PathBuf>,
+) -> Error<'a> {
+    match err {
+        TranslateError::Input(io_err) => Error::Input(input, io_err),
+        TranslateError::Output(io_err) => Error::Output(output, io_err),
+        TranslateError::Expr(ExprError::Output(io_err)) => Error::Output(output, io_err),
+        TranslateError::Expr(ExprError::Input(io_err)) => Error::Input(input, io_err),
+        TranslateError::Expr(ExprError::Vars(vars_err)) => Error::Vars(vars_file, vars_err),
+        TranslateError::Expr(ExprError::Internal(expr_err)) => Error::Expr(expr_err),
+        TranslateError::Expr(ExprError::Include(_)) => unreachable!(),
+        TranslateError::Vars(expr_err) => Error::Vars(vars_file, expr_err),
+    }
+}
+
+/// prints an error that happened at `location` of the input, or in a template included from there
+fn print_error(location: &str, includes: &[(PathBuf, usize, usize)], err: Error, secrets: &Secrets) {
+    let err = secrets.mask_str(&err.to_string());
+    match includes.split_last() {
+        None => eprintln!(r#"Error [{}] {}"#, location, err),
+        Some(((path, line, col), outer)) => {
+            // innermost file first, followed by the chain of templates that included it
+            eprintln!(r#"Error [{}:{}:{}] {}"#, path.display(), line, col, err);
+            for (path, line, col) in outer.iter().rev() {
+                eprintln!(r#"  included from {}:{}:{}"#, path.display(), line, col);
+            }
+            eprintln!(r#"  included from {}"#, location);
+        }
     }
-    Ok(())
 }
 
 fn main() {
     let stdin = io::stdin();
     let stdout = io::stdout();
-    if let Err(err) = real_main(&stdin, &stdout) {
-        eprintln!("{}", err);
-        exit(1);
+    let (opt, command) = cli::from_args();
+    // with --diff and --check, 1 means that the output would change
+    let code = if opt.diff || opt.check { 2 } else { 1 };
+    let secrets = Secrets::from_env(&opt.secret);
+    if let Err(err) = real_main(opt, command, &stdin, &stdout) {
+        eprintln!("{}", secrets.mask_str(&err.to_string()));
+        // removes the temporary output file
+        drop(err);
+        exit(code);
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use structopt::StructOpt;
+
+    use super::{cli, cli_args, render_all, Args, Outcome, Secrets};
+    use crate::parse::testing::{lock_env, temp_dir};
+
+    #[test]
+    fn vars_from_stdin_need_a_template_file() {
+        let (stdin, stdout) = (io::stdin(), io::stdout());
+        let error = |args: &[&str]| match cli_args(&cli::Opt::from_iter(args), &stdin, &stdout) {
+            Ok(_) => String::new(),
+            Err(error) => error.to_string(),
+        };
+        let usage = "--vars-file - reads the vars from stdin, the template needs --input-file or --render";
+        assert!(error(&["kay", "--vars-file", "-"]).contains(usage));
+        assert!(error(&["kay", "--vars-file", "-", "-o", "out"]).contains(usage));
+        // the template file is opened, and fails, before stdin is read
+        let missing = error(&["kay", "--vars-file", "-", "-i", "/nonexistent/kay.tmpl"]);
+        assert!(!missing.is_empty() && !missing.contains(usage), "{}", missing);
+        let missing = error(&["kay", "--vars-file", "-", "--render", "/nonexistent/kay.tmpl:out"]);
+        assert!(!missing.is_empty() && !missing.contains(usage), "{}", missing);
+    }
+
+    #[test]
+    fn errors_before_translating_mask_env_secrets() {
+        let _env = lock_env();
+        std::env::set_var("KAY_MAIN_TOKEN", "t0k3n");
+        let secrets = Secrets::from_env(&[String::from("*_token")]);
+        let (stdin, stdout) = (io::stdin(), io::stdout());
+        let opt = cli::Opt::from_iter(&["kay", "--vars-exec", "echo t0k3n; exit 1", "-i", "Cargo.toml"]);
+        let error = match cli_args(&opt, &stdin, &stdout) {
+            Ok(_) => String::new(),
+            Err(error) => secrets.mask_str(&error.to_string()),
+        };
+        assert_eq!(error, r#"--vars-exec "echo ****; exit 1" failed: exit status: 1"#);
+    }
+
+    #[test]
+    fn targets_are_written_once_every_template_rendered() {
+        let dir = temp_dir("render-all");
+        std::fs::write(dir.join("a.tmpl"), "a\n").unwrap();
+        std::fs::write(dir.join("b.tmpl"), "${var $.missing}\n").unwrap();
+        std::fs::write(dir.join("a.out"), "old\n").unwrap();
+        let (stdin, stdout) = (io::stdin(), io::stdout());
+        let render = |templates: &[&str], mode: &str| {
+            let mut args = vec![String::from("kay"), String::from(mode)];
+            for (n, template) in templates.iter().enumerate() {
+                let out = ["a.out", "b.out"][n];
+                args.extend([String::from("--render"), format!("{}:{}", dir.join(template).display(), dir.join(out).display())]);
+            }
+            let Args { renders, options } = cli_args(&cli::Opt::from_iter(&args), &stdin, &stdout).ok().unwrap();
+            render_all(renders, &options).0
+        };
+        for mode in ["--atomic", "--check"] {
+            assert!(matches!(render(&["a.tmpl", "b.tmpl"], mode), Outcome::Failed));
+            assert_eq!(std::fs::read_to_string(dir.join("a.out")).unwrap(), "old\n");
+            assert!(!dir.join("b.out").exists());
+        }
+        // only the rendered files are left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        assert!(matches!(render(&["a.tmpl", "a.tmpl"], "--atomic"), Outcome::Rendered));
+        assert_eq!(std::fs::read_to_string(dir.join("a.out")).unwrap(), "a\n");
+        assert_eq!(std::fs::read_to_string(dir.join("b.out")).unwrap(), "a\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,69 @@
-use super::{Vars, VarsError};
+use super::{PositionIterator, ReadChars, Value, Vars, VarsError, VarsFormat};
 use std::io::{self, Write};
+use std::path::PathBuf;
 
+mod env;
+mod envsubst;
+mod escape;
+mod expansion;
 mod expr;
+mod file;
+mod filter;
+mod include;
+mod lexer;
+mod macros;
+mod ops;
+mod parser;
+mod pattern;
+mod scanner;
+mod scope;
+mod secrets;
+mod structured;
+mod trim;
 
-use expr::{translate_expr};
-pub use expr::{ExprError, ExprInternalError};
+use expr::{evaluate, is_comment, is_identifier, keyword, translate_expr, unquote};
+pub use env::EnvPolicy;
+pub use envsubst::{shell_format_variables, translate_envsubst};
+pub use escape::{Escape, EscapeMode};
+pub use expr::{ExprError, ExprInternalError, Span};
+pub use file::FilePolicy;
+pub use pattern::matches;
+pub use include::IncludeError;
+use macros::Macro;
+pub use scanner::Delimiters;
+use scanner::{Scanner, Token, MAGIC_COMMENT_LIMIT};
+pub use scope::Scope;
+pub use secrets::{Secrets, MASK};
+pub use structured::{translate_document, write_document};
+use trim::Trim;
+
+/// everything an expression may read from while translating
+pub struct Context<'a> {
+    pub vars: &'a dyn Vars,
+    pub files: &'a FilePolicy,
+    pub env: &'a EnvPolicy,
+    /// collects the secret values read, to mask them in diagnostics
+    pub secrets: &'a Secrets,
+    /// escaping applied to every substituted value
+    pub escape: Escape,
+    /// delimiters of templates without a `kay:delimiters` comment
+    pub delimiters: Delimiters,
+    /// remove the lines that hold nothing but a block tag such as `${define ...}` or `${end}`
+    pub trim_blocks: bool,
+    /// directory of the template being translated, includes are resolved from here
+    pub dir: PathBuf,
+    /// canonical path of the template being translated, `None` when reading from stdin
+    pub template: Option<PathBuf>,
+    /// context of the including template
+    pub parent: Option<&'a Context<'a>>,
+}
+
+impl<'a> Context<'a> {
+    /// this context followed by the contexts of all including templates
+    pub fn ancestors(&self) -> impl Iterator<Item = &Context<'a>> {
+        std::iter::successors(Some(self), |ctx| ctx.parent)
+    }
+}
 
 pub enum TranslateError {
     Input(io::Error),
@@ -13,6 +72,20 @@ pub enum TranslateError {
     Vars(VarsError),
 }
 
+impl TranslateError {
+    /// strips the include wrappers, returning the innermost error
+    /// and the includes it happened in, outermost first
+    pub fn unwind_includes(self) -> (TranslateError, Vec<(PathBuf, usize, usize)>) {
+        let mut error = self;
+        let mut includes = Vec::new();
+        while let TranslateError::Expr(ExprError::Include(include)) = error {
+            includes.push((include.path, include.line, include.col));
+            error = *include.error;
+        }
+        (error, includes)
+    }
+}
+
 impl From<VarsError> for TranslateError {
     fn from(from: VarsError) -> TranslateError {
         TranslateError::Vars(from)
@@ -23,47 +96,403 @@ impl From<ExprError> for TranslateError {
         TranslateError::Expr(from)
     }
 }
+impl From<TranslateError> for ExprError {
+    fn from(from: TranslateError) -> ExprError {
+        match from {
+            TranslateError::Input(e) => ExprError::Input(e),
+            TranslateError::Output(e) => ExprError::Output(e),
+            TranslateError::Expr(e) => e,
+            TranslateError::Vars(e) => ExprError::Vars(e),
+        }
+    }
+}
 
 pub fn translate<R: Iterator<Item = io::Result<char>>, W: Write>(
-    input_chars: &mut R,
+    input_chars: &mut PositionIterator<R>,
     output: &mut W,
-    vars: &Box<dyn Vars>,
+    ctx: &Context,
+    scope: &mut Scope,
 ) -> Result<(), TranslateError> {
-    let mut slash = false;
-    let mut dollar = false;
-
-    while let Some(rch) = input_chars.next() {
-        match rch {
-            Err(e) => return Err(TranslateError::Input(e)),
-            Ok(ch) => {
-                if slash {
-                    if ch == '$' {
-                        output.write(b"$").map_err(TranslateError::Output)?;
-                    } else if ch == '\\' {
-                        output.write(b"\\\\").map_err(TranslateError::Output)?;
-                    }
-                    slash = false;
-                } else if dollar {
-                    if ch == '{' {
-                        translate_expr(input_chars, output, vars)?;
-                    }
-                    dollar = false
-                } else if ch == '\\' {
-                    slash = true;
-                } else if ch == '$' {
-                    dollar = true;
-                } else {
-                    let mut buf = [0u8, 0, 0, 0];
-                    let s = ch.encode_utf8(&mut buf);
-                    output.write(s.as_bytes()).map_err(TranslateError::Output)?;
+    // a `kay:delimiters` comment on the first line chooses the delimiters of this template
+    match Delimiters::from_magic_comment(&input_chars.peek_line(MAGIC_COMMENT_LIMIT)) {
+        Some(delimiters) => {
+            let mut delimiters = delimiters.map_err(|e| ExprError::from(ExprInternalError::InvalidDelimiters(e)))?;
+            delimiters.legacy_escapes = ctx.delimiters.legacy_escapes;
+            // the comment line itself is not written
+            for ch in input_chars.by_ref() {
+                if ch.map_err(TranslateError::Input)? == '\n' {
+                    break;
                 }
             }
+            let mut tokens = Trim::new(Scanner::new(input_chars, &delimiters), ctx.trim_blocks);
+            translate_tokens(&mut tokens, output, ctx, scope)
+        }
+        None => {
+            let mut tokens = Trim::new(Scanner::new(input_chars, &ctx.delimiters), ctx.trim_blocks);
+            translate_tokens(&mut tokens, output, ctx, scope)
+        }
+    }
+}
+
+/// collects the tokens up to the `${end}` matching an already consumed block tag
+fn collect_block<T: Iterator<Item = Result<Token, TranslateError>>>(
+    tokens: &mut T,
+    block: &str,
+) -> Result<Vec<Token>, TranslateError> {
+    let mut body = Vec::new();
+    let mut depth = 0usize;
+    for token in tokens {
+        let token = token?;
+        if let Token::Tag(tag) = &token {
+            match keyword(tag).0 {
+                "define" | "if" | "for" => depth += 1,
+                "end" if depth == 0 => return Ok(body),
+                "end" => depth -= 1,
+                _ => {}
+            }
+        }
+        body.push(token);
+    }
+    Err(ExprError::from(ExprInternalError::UnclosedBlock(block.into())).into())
+}
+
+/// splits the body of an `${if ...}` into the tokens before and after its `${else}`
+fn split_else(mut body: Vec<Token>) -> (Vec<Token>, Vec<Token>) {
+    let mut depth = 0usize;
+    let position = body.iter().position(|token| match token {
+        Token::Tag(tag) => match keyword(tag) {
+            ("define", _) | ("if", _) | ("for", _) => {
+                depth += 1;
+                false
+            }
+            ("end", _) => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            ("else", "") => depth == 0,
+            _ => false,
+        },
+        Token::Text(_) => false,
+    });
+    match position {
+        Some(i) => {
+            let otherwise = body.split_off(i + 1);
+            body.pop();
+            (body, otherwise)
         }
+        None => (body, Vec::new()),
     }
-    if slash {
-        output.write(b"\\").map_err(TranslateError::Output)?;
-    } else if dollar {
-        output.write(b"$").map_err(TranslateError::Output)?;
+}
+
+/// splits `name in expr` of a `${for ...}` tag
+fn parse_for(spec: &str) -> Result<(&str, &str), ExprInternalError> {
+    let (name, rest) = keyword(spec);
+    match keyword(rest) {
+        ("in", expr) if is_identifier(name) && !expr.is_empty() => Ok((name, expr)),
+        _ => Err(ExprInternalError::InvalidSyntax(format!("for {}", spec))),
+    }
+}
+
+/// translates the body of an `${if ...}` or `${for ...}` in a frame of its own, with `binding` bound in it
+fn translate_block<W: Write>(
+    body: Vec<Token>,
+    binding: Option<(&str, Value)>,
+    output: &mut W,
+    ctx: &Context,
+    scope: &mut Scope,
+) -> Result<(), TranslateError> {
+    scope.push_block();
+    if let Some((name, value)) = binding {
+        scope.bind(name.into(), value);
+    }
+    let result = translate_tokens(&mut body.into_iter().map(Ok), output, ctx, scope);
+    scope.pop_block();
+    result
+}
+
+/// splits `name = expr` of a `${let ...}` tag
+fn parse_let(binding: &str) -> Result<(&str, &str), ExprInternalError> {
+    match binding.find('=') {
+        Some(i) if is_identifier(binding[..i].trim()) => Ok((binding[..i].trim(), binding[i + 1..].trim())),
+        _ => Err(ExprInternalError::InvalidSyntax(format!("let {}", binding))),
+    }
+}
+
+pub fn translate_tokens<T: Iterator<Item = Result<Token, TranslateError>>, W: Write>(
+    tokens: &mut T,
+    output: &mut W,
+    ctx: &Context,
+    scope: &mut Scope,
+) -> Result<(), TranslateError> {
+    while let Some(token) = tokens.next() {
+        match token? {
+            Token::Text(text) => output.write_all(text.as_bytes()).map_err(TranslateError::Output)?,
+            Token::Tag(ref tag) if is_comment(tag) => {}
+            Token::Tag(tag) => match keyword(&tag) {
+                ("define", signature) => {
+                    let body = collect_block(tokens, &tag)?;
+                    scope.define(Macro::parse(signature, body, ctx).map_err(ExprError::from)?);
+                }
+                ("if", condition) => {
+                    let (then, otherwise) = split_else(collect_block(tokens, &tag)?);
+                    let branch = if evaluate(condition, ctx, scope)?.is_truthy() { then } else { otherwise };
+                    translate_block(branch, None, output, ctx, scope)?;
+                }
+                ("for", spec) => {
+                    let body = collect_block(tokens, &tag)?;
+                    let (name, expr) = parse_for(spec).map_err(ExprError::from)?;
+                    let items = match evaluate(expr, ctx, scope)? {
+                        Value::List(items) => items,
+                        Value::Null => Vec::new(),
+                        value => return Err(ExprError::from(ExprInternalError::NotIterable(value.type_name())).into()),
+                    };
+                    for item in items {
+                        translate_block(body.clone(), Some((name, item)), output, ctx, scope)?;
+                    }
+                }
+                (keyword @ "end", "") | (keyword @ "else", "") => {
+                    return Err(ExprError::from(ExprInternalError::UnexpectedEnd(keyword.into())).into())
+                }
+                // the scanner already wrote the region in between as text
+                ("raw", "") | ("endraw", "") => {}
+                ("import", path) => include::import(unquote(path), ctx, scope)?,
+                ("let", binding) => {
+                    let (name, expr) = parse_let(binding).map_err(ExprError::from)?;
+                    let value = evaluate(expr, ctx, scope)?;
+                    scope.bind(name.into(), value);
+                }
+                _ => translate_expr(&tag, output, ctx, scope)?,
+            },
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+pub mod testing {
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, MutexGuard};
+
+    use serde_json::Value as JsonValue;
+
+    use super::{translate, Context, Delimiters, EnvPolicy, Escape, FilePolicy, PositionIterator, Scope, Secrets, TranslateError, Vars};
+    use crate::vars::{EmptyVars, JsonVars};
+
+    /// how a test translates, by default without vars, from the working directory, reading no files
+    pub struct Setup {
+        pub delimiters: Delimiters,
+        pub escape: Escape,
+        pub trim_blocks: bool,
+        pub env: EnvPolicy,
+        /// the vars document, none by default
+        pub vars: Option<JsonValue>,
+        /// the translated template file, files may be read from its directory
+        pub template: Option<PathBuf>,
+    }
+
+    impl Default for Setup {
+        fn default() -> Self {
+            Setup {
+                delimiters: Delimiters::default(),
+                escape: Escape::None,
+                trim_blocks: false,
+                env: EnvPolicy::new(Vec::new(), Vec::new(), true),
+                vars: None,
+                template: None,
+            }
+        }
+    }
+
+    impl Setup {
+        /// runs `f` with the context and scope of a template translated with this setup
+        pub fn with_context<T, F: FnOnce(&Context, &mut Scope) -> T>(&self, f: F) -> T {
+            let dir = match &self.template {
+                Some(template) => template.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+                None => PathBuf::from("."),
+            };
+            let allowed = self.template.iter().map(|_| dir.clone()).collect();
+            let vars: Box<dyn Vars> = match &self.vars {
+                Some(json) => {
+                    let mut vars = JsonVars::new();
+                    vars.merge(json.clone(), "test").unwrap_or_else(|_| panic!("the vars are a map"));
+                    Box::new(vars)
+                }
+                None => Box::new(EmptyVars::new()),
+            };
+            let files = FilePolicy::new(None, allowed, dir.clone());
+            let secrets = Secrets::new(&[]);
+            let ctx = Context {
+                vars: vars.as_ref(),
+                files: &files,
+                env: &self.env,
+                secrets: &secrets,
+                escape: self.escape,
+                delimiters: self.delimiters.clone(),
+                trim_blocks: self.trim_blocks,
+                dir,
+                template: self.template.as_ref().map(|template| template.canonicalize().unwrap()),
+                parent: None,
+            };
+            f(&ctx, &mut Scope::new())
+        }
+
+        /// translates `input`, an error comes with the line and column it was found at
+        pub fn translate<I: Iterator<Item = io::Result<char>>>(
+            &self,
+            input: I,
+            output: &mut Vec<u8>,
+        ) -> Result<(), (TranslateError, usize, usize)> {
+            self.with_context(|ctx, scope| {
+                let mut input_chars = PositionIterator::from(input);
+                translate(&mut input_chars, output, ctx, scope)
+                    .map_err(|error| (error, input_chars.line(), input_chars.col()))
+            })
+        }
+
+        pub fn translate_str(&self, template: &str) -> Result<String, TranslateError> {
+            let mut output = Vec::new();
+            self.translate(template.chars().map(Ok), &mut output).map_err(|(error, _, _)| error)?;
+            Ok(String::from_utf8(output).expect("templates are utf8"))
+        }
+    }
+
+    pub fn translate_chars<I: Iterator<Item = io::Result<char>>>(
+        input: I,
+        output: &mut Vec<u8>,
+        delimiters: Delimiters,
+        escape: Escape,
+    ) -> Result<(), (TranslateError, usize, usize)> {
+        Setup { delimiters, escape, ..Setup::default() }.translate(input, output)
+    }
+
+    pub fn translate_str(template: &str, delimiters: Delimiters, escape: Escape) -> Result<String, TranslateError> {
+        Setup { delimiters, escape, ..Setup::default() }.translate_str(template)
+    }
+
+    /// translates the template file at `path`, it may read the files of its directory
+    pub fn render_file(path: &Path) -> Result<String, TranslateError> {
+        let template = fs::read_to_string(path).unwrap();
+        Setup { template: Some(path.into()), ..Setup::default() }.translate_str(&template)
+    }
+
+    pub fn render(template: &str) -> String {
+        translate_str(template, Delimiters::default(), Escape::None)
+            .unwrap_or_else(|_| panic!("translating {:?} failed", template))
+    }
+
+    pub fn render_err(template: &str) -> TranslateError {
+        match translate_str(template, Delimiters::default(), Escape::None) {
+            Ok(output) => panic!("translating {:?} gave {:?}", template, output),
+            Err(error) => error,
+        }
+    }
+
+    /// tests run in parallel, every test changing the environment holds this lock
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// locks the environment for the rest of the test, until the guard is dropped
+    pub fn lock_env() -> MutexGuard<'static, ()> {
+        ENV.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// an empty directory for one test
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kay-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::testing::{render, render_err, translate_chars, Setup};
+    use super::{Delimiters, Escape, ExprError, ExprInternalError, TranslateError};
+
+    fn expr_error(template: &str) -> ExprInternalError {
+        match render_err(template) {
+            TranslateError::Expr(ExprError::Internal(error)) => error,
+            _ => panic!("{:?} did not fail in an expression", template),
+        }
+    }
+
+    fn error_position(template: &str) -> (usize, usize) {
+        match translate_chars(template.chars().map(Ok), &mut Vec::new(), Delimiters::default(), Escape::None) {
+            Ok(()) => panic!("translating {:?} succeeded", template),
+            Err((_, line, col)) => (line, col),
+        }
+    }
+
+    #[test]
+    fn magic_comment() {
+        assert_eq!(render("# kay:delimiters [[ ]]\n[[ 1 + 1 ]] ${x}\n"), "2 ${x}\n");
+        assert_eq!(render("// kay:delimiters <% %> @\n@<% <% 1 %>"), "<% 1");
+        // only the start of the first line is looked at
+        let late = format!("{}kay:delimiters [[ ]]\n${{1}}", " ".repeat(300));
+        assert_eq!(render(&late), format!("{}kay:delimiters [[ ]]\n1", " ".repeat(300)));
+    }
+
+    #[test]
+    fn errors_on_the_first_line_keep_their_position() {
+        assert_eq!(error_position("hello ${env NO_SUCH_VARIABLE_HERE} world"), (1, 35));
+        assert_eq!(error_position("hello\n${1 / 0}"), (2, 9));
+        assert_eq!(error_position("# kay:delimiters [[ ]]\n[[ 1 / 0 ]]"), (2, 12));
+    }
+
+    #[test]
+    fn a_single_line_streams() {
+        // the input fails after its first expression, which is written before
+        let input = "a ${1 + 1} b".chars().map(Ok).chain(std::iter::once(Err(io::Error::other("closed"))));
+        let mut output = Vec::new();
+        assert!(translate_chars(input, &mut output, Delimiters::default(), Escape::None).is_err());
+        assert_eq!(output, b"a 2");
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(render("${if 1 < 2}yes${end}"), "yes");
+        assert_eq!(render("${if 0}yes${else}no${end}"), "no");
+        assert_eq!(render("${if \"\"}yes${end}."), ".");
+        assert_eq!(render("${if true}${if false}a${else}b${end}${else}c${end}"), "b");
+        assert_eq!(render("${if false}${define m()}${end}a${else}b${end}"), "b");
+        assert!(matches!(expr_error("${if true}a"), ExprInternalError::UnclosedBlock(_)));
+        assert!(matches!(expr_error("a${else}b"), ExprInternalError::UnexpectedEnd(ref tag) if tag == "else"));
+    }
+
+    #[test]
+    fn loops() {
+        let setup = Setup {
+            vars: Some(serde_json::json!({"items": ["a", "b"], "nested": [[1, 2], [3]], "empty": []})),
+            ..Setup::default()
+        };
+        let render = |template: &str| setup.translate_str(template).ok().unwrap();
+        assert_eq!(render("${for x in var $.items}<${x}>${end}"), "<a><b>");
+        assert_eq!(render("${for l in var $.nested}${for x in l}${x}${end};${end}"), "12;3;");
+        assert_eq!(render("${for x in var $.empty}${x}${end}."), ".");
+        // the loop variable and the bindings of an iteration end with it
+        assert_eq!(render("${let x = 0}${for x in var $.items}${let y = x}${y}${end}${x}"), "ab0");
+        let list = "\"a b c\" | upper";
+        assert!(matches!(expr_error(&format!("${{for x in {}}}${{end}}", list)), ExprInternalError::NotIterable("string")));
+        assert_eq!(render("${for x in null}${x}${end}."), ".");
+        assert!(matches!(expr_error("${for in x}${end}"), ExprInternalError::InvalidSyntax(_)));
+        assert!(matches!(expr_error("${for x}${end}"), ExprInternalError::InvalidSyntax(_)));
+    }
+
+    #[test]
+    fn bindings_are_scoped_to_blocks() {
+        // a binding in a block shadows the outer one until the block ends
+        assert_eq!(render("${let x = 1}${if true}${let x = 2}${x}${end}${x}"), "21");
+        assert_eq!(render("${let x = 1}${if false}${else}${let x = x + 1}${x}${end}${x}"), "21");
+        // outer bindings are visible in nested blocks, block bindings do not leak out
+        assert_eq!(render("${let x = 1}${if true}${if true}${x}${end}${end}"), "1");
+        assert!(matches!(expr_error("${if true}${let y = 1}${end}${y}"), ExprInternalError::UnknownName(ref name) if name == "y"));
+        // a block in a macro sees the macro's frame, the call still does not see its caller's blocks
+        assert_eq!(render("${define m(a)}${if true}${let b = a}${b}${end}${end}${call m(3)}"), "3");
+        let template = "${define m()}${y}${end}${if true}${let y = 1}${call m()}${end}";
+        assert!(matches!(expr_error(template), ExprInternalError::UnknownName(ref name) if name == "y"));
+    }
+}
@@ -0,0 +1,33 @@
+use super::expr::env;
+use super::pattern::matches;
+use super::{ExprInternalError, Value};
+
+/// the environment variables templates may read
+pub struct EnvPolicy {
+    /// patterns of the readable variables, every variable when empty
+    allow: Vec<String>,
+    /// patterns of variables that are never read, even when allowed
+    deny: Vec<String>,
+    /// `false` denies every variable
+    enabled: bool,
+}
+
+impl EnvPolicy {
+    pub fn new(allow: Vec<String>, deny: Vec<String>, enabled: bool) -> Self {
+        EnvPolicy { allow, deny, enabled }
+    }
+
+    pub fn is_allowed(&self, name: &str) -> bool {
+        self.enabled
+            && (self.allow.is_empty() || self.allow.iter().any(|pattern| matches(pattern, name)))
+            && !self.deny.iter().any(|pattern| matches(pattern, name))
+    }
+
+    /// the value of the variable, `None` when it is not set
+    pub fn get(&self, name: &str) -> Result<Option<Value>, ExprInternalError> {
+        if !self.is_allowed(name) {
+            return Err(ExprInternalError::EnvAccessDenied(name.into()));
+        }
+        Ok(env(name))
+    }
+}
@@ -0,0 +1,233 @@
+use std::io::{self, Write};
+
+use super::scanner::Token;
+use super::{Context, Escape, TranslateError};
+
+fn is_name_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_name(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// splits the input like GNU envsubst: `$NAME` and `${NAME}` are tags holding `NAME` / `{NAME}`,
+/// everything else, including a `$` not followed by a name, is text
+pub struct EnvsubstScanner<'a, R> {
+    input_chars: &'a mut R,
+    /// a character read ahead while looking for the end of a name
+    peeked: Option<char>,
+    pending: Option<Token>,
+}
+
+impl<'a, R: Iterator<Item = io::Result<char>>> EnvsubstScanner<'a, R> {
+    pub fn new(input_chars: &'a mut R) -> Self {
+        EnvsubstScanner {
+            input_chars,
+            peeked: None,
+            pending: None,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<io::Result<char>> {
+        match self.peeked.take() {
+            Some(ch) => Some(Ok(ch)),
+            None => self.input_chars.next(),
+        }
+    }
+
+    /// reads the rest of a name, the character ending it is kept for the next read
+    fn read_name(&mut self, name: &mut String) -> io::Result<()> {
+        while let Some(ch) = self.next_char() {
+            let ch = ch?;
+            if !is_name(ch) {
+                self.peeked = Some(ch);
+                break;
+            }
+            name.push(ch);
+        }
+        Ok(())
+    }
+
+    /// reads what follows a `$`, the tag or the literal text it turned out to be
+    fn read_variable(&mut self) -> io::Result<Token> {
+        match self.next_char().transpose()? {
+            Some('{') => {}
+            Some(ch) if is_name_start(ch) => {
+                let mut name = ch.to_string();
+                self.read_name(&mut name)?;
+                return Ok(Token::Tag(name));
+            }
+            Some(ch) => {
+                self.peeked = Some(ch);
+                return Ok(Token::Text("$".into()));
+            }
+            None => return Ok(Token::Text("$".into())),
+        }
+        let mut name = String::new();
+        match self.next_char().transpose()? {
+            Some(ch) if is_name_start(ch) => {
+                name.push(ch);
+                self.read_name(&mut name)?;
+            }
+            Some(ch) => self.peeked = Some(ch),
+            None => {}
+        }
+        match self.next_char().transpose()? {
+            Some('}') if !name.is_empty() => Ok(Token::Tag(format!("{{{}}}", name))),
+            Some(ch) => {
+                self.peeked = Some(ch);
+                Ok(Token::Text(format!("${{{}", name)))
+            }
+            None => Ok(Token::Text(format!("${{{}", name))),
+        }
+    }
+}
+
+impl<'a, R: Iterator<Item = io::Result<char>>> Iterator for EnvsubstScanner<'a, R> {
+    type Item = Result<Token, TranslateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+        let mut text = String::new();
+        while let Some(ch) = self.next_char() {
+            let ch = match ch {
+                Ok(ch) => ch,
+                Err(e) => return Some(Err(TranslateError::Input(e))),
+            };
+            if ch == '$' {
+                match self.read_variable() {
+                    Ok(Token::Text(literal)) => text.push_str(&literal),
+                    Ok(tag) if text.is_empty() => return Some(Ok(tag)),
+                    Ok(tag) => {
+                        self.pending = Some(tag);
+                        return Some(Ok(Token::Text(text)));
+                    }
+                    Err(e) => return Some(Err(TranslateError::Input(e))),
+                }
+            } else {
+                text.push(ch);
+                if ch == '\n' {
+                    return Some(Ok(Token::Text(text)));
+                }
+            }
+        }
+        if text.is_empty() {
+            None
+        } else {
+            Some(Ok(Token::Text(text)))
+        }
+    }
+}
+
+fn variable_name(tag: &str) -> &str {
+    tag.trim_start_matches('{').trim_end_matches('}')
+}
+
+/// the variables referenced by a SHELL-FORMAT argument such as `'$HOST ${PORT}'`
+pub fn shell_format_variables(shell_format: &str) -> Vec<String> {
+    let mut chars = shell_format.chars().map(Ok);
+    EnvsubstScanner::new(&mut chars)
+        .filter_map(|token| match token {
+            Ok(Token::Tag(tag)) => Some(variable_name(&tag).into()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// substitutes environment variables like GNU envsubst, variables that are not set are replaced
+/// by nothing, with `variables` only those are substituted and every other reference is kept
+pub fn translate_envsubst<R: Iterator<Item = io::Result<char>>, W: Write>(
+    input_chars: &mut R,
+    output: &mut W,
+    ctx: &Context,
+    variables: Option<&[String]>,
+) -> Result<(), TranslateError> {
+    let substituted = |name: &str| variables.is_none_or(|variables| variables.iter().any(|v| v == name));
+    for token in EnvsubstScanner::new(input_chars) {
+        let text = match token? {
+            Token::Text(text) => text.into_bytes(),
+            Token::Tag(tag) if !substituted(variable_name(&tag)) => format!("${}", tag).into_bytes(),
+            Token::Tag(tag) => match ctx.env.get(variable_name(&tag)).map_err(|e| TranslateError::Expr(e.into()))? {
+                None => Vec::new(),
+                Some(value) => {
+                    ctx.secrets.lookup(variable_name(&tag), &value);
+                    match ctx.escape {
+                        Escape::None => value.into_bytes(),
+                        escape => escape.escape(&value.to_string()).into_bytes(),
+                    }
+                }
+            },
+        };
+        output.write_all(&text).map_err(TranslateError::Output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{lock_env, Setup};
+    use super::super::{EnvPolicy, ExprError, ExprInternalError};
+    use super::{shell_format_variables, translate_envsubst, TranslateError};
+
+    /// substitutes `input` with `KAY_ES_A=1 KAY_ES_B=2 KAY_ES_C=3` set
+    fn envsubst(input: &str, shell_format: Option<&str>, env: EnvPolicy) -> Result<String, TranslateError> {
+        let _env = lock_env();
+        std::env::set_var("KAY_ES_A", "1");
+        std::env::set_var("KAY_ES_B", "2");
+        std::env::set_var("KAY_ES_C", "3");
+        std::env::remove_var("KAY_ES_UNSET");
+        let variables = shell_format.map(shell_format_variables);
+        let setup = Setup { env, ..Setup::default() };
+        let mut output = Vec::new();
+        setup.with_context(|ctx, _| translate_envsubst(&mut input.chars().map(Ok), &mut output, ctx, variables.as_deref()))?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn substitute(input: &str) -> String {
+        envsubst(input, None, EnvPolicy::new(vec![], vec![], true)).ok().unwrap()
+    }
+
+    #[test]
+    fn like_envsubst() {
+        // expected outputs are those of GNU envsubst
+        assert_eq!(substitute("$KAY_ES_A$KAY_ES_B"), "12");
+        assert_eq!(substitute("${KAY_ES_A}x"), "1x");
+        assert_eq!(substitute("$ {KAY_ES_A}"), "$ {KAY_ES_A}");
+        assert_eq!(substitute("$$KAY_ES_A"), "$1");
+        assert_eq!(substitute("a$"), "a$");
+        assert_eq!(substitute("${KAY_ES_A"), "${KAY_ES_A");
+        assert_eq!(substitute("${KAY_ES_A x}"), "${KAY_ES_A x}");
+        assert_eq!(substitute("${} ${1} $1"), "${} ${1} $1");
+        assert_eq!(substitute("$KAY_ES_UNSET. ${KAY_ES_UNSET}"), ". ");
+        assert_eq!(substitute("$KAY_ES_A_B"), "");
+        assert_eq!(substitute("a\nb $KAY_ES_A\n"), "a\nb 1\n");
+    }
+
+    #[test]
+    fn shell_format() {
+        assert_eq!(shell_format_variables("$HOST ${PORT} $ {X} text $"), ["HOST", "PORT"]);
+        let substituted = envsubst(
+            "$KAY_ES_A $KAY_ES_B ${KAY_ES_C} ${KAY_ES_B}",
+            Some("$KAY_ES_A ${KAY_ES_C}"),
+            EnvPolicy::new(vec![], vec![], true),
+        );
+        assert_eq!(substituted.ok().unwrap(), "1 $KAY_ES_B 3 ${KAY_ES_B}");
+    }
+
+    #[test]
+    fn env_policy() {
+        let denied = |policy: EnvPolicy| match envsubst("x $KAY_ES_A", None, policy) {
+            Err(TranslateError::Expr(ExprError::Internal(ExprInternalError::EnvAccessDenied(name)))) => name == "KAY_ES_A",
+            _ => false,
+        };
+        assert!(denied(EnvPolicy::new(vec![], vec![], false)));
+        assert!(denied(EnvPolicy::new(vec![], vec![String::from("KAY_ES_*")], true)));
+        assert!(denied(EnvPolicy::new(vec![String::from("KAY_ES_B")], vec![], true)));
+        // variables left out of the SHELL-FORMAT are kept without being read
+        let kept = envsubst("$KAY_ES_A $KAY_ES_B", Some("${KAY_ES_B}"), EnvPolicy::new(vec![String::from("KAY_ES_B")], vec![], true));
+        assert_eq!(kept.ok().unwrap(), "$KAY_ES_A 2");
+    }
+}
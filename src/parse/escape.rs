@@ -0,0 +1,199 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::str;
+
+/// syntax every substituted value is escaped for, the template provides the quotes around json and yaml
+/// strings, a shell value becomes a quoted word of its own
+#[derive(Clone, Copy, PartialEq)]
+pub enum Escape {
+    None,
+    /// contents of a json string
+    Json,
+    /// contents of a double quoted yaml scalar
+    Yaml,
+    /// a single quoted posix shell word
+    Shell,
+    /// xml / html text or attribute value
+    Xml,
+    /// a percent-encoded url component
+    Url,
+}
+
+/// `--escape`: a syntax, or `auto` to infer it from the output or template file name
+#[derive(Debug, Clone, Copy)]
+pub enum EscapeMode {
+    Auto,
+    Syntax(Escape),
+}
+
+impl str::FromStr for EscapeMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(EscapeMode::Auto),
+            s => s.parse().map(EscapeMode::Syntax).map_err(|_| {
+                String::from(r#"only "auto", "none", "json", "yaml", "shell", "xml", "html" and "url" are supported"#)
+            }),
+        }
+    }
+}
+
+impl Escape {
+    /// infers the escaping from an output or template file name,
+    /// template suffixes such as `config.json.tmpl` are looked through
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Escape> {
+        let path = path.as_ref();
+        match path.extension().and_then(OsStr::to_str) {
+            Some("json") => Some(Escape::Json),
+            Some("yaml") | Some("yml") => Some(Escape::Yaml),
+            Some("sh") | Some("bash") => Some(Escape::Shell),
+            Some("xml") | Some("html") | Some("htm") => Some(Escape::Xml),
+            Some("tmpl") | Some("tpl") | Some("template") => path.file_stem().and_then(Escape::from_extension),
+            _ => None,
+        }
+    }
+
+    pub fn escape(self, value: &str) -> String {
+        match self {
+            Escape::None => value.into(),
+            Escape::Json => {
+                let quoted = serde_json::to_string(value).unwrap_or_default();
+                quoted[1..quoted.len() - 1].into()
+            }
+            Escape::Yaml => yaml(value),
+            // a `'` ends the quoted word, is escaped and starts a new one
+            Escape::Shell => format!("'{}'", value.replace('\'', r"'\''")),
+            Escape::Xml => value
+                .chars()
+                .fold(String::with_capacity(value.len()), |mut escaped, ch| {
+                    match ch {
+                        '&' => escaped.push_str("&amp;"),
+                        '<' => escaped.push_str("&lt;"),
+                        '>' => escaped.push_str("&gt;"),
+                        '"' => escaped.push_str("&quot;"),
+                        '\'' => escaped.push_str("&#39;"),
+                        ch => escaped.push(ch),
+                    }
+                    escaped
+                }),
+            Escape::Url => value.bytes().fold(String::with_capacity(value.len()), |mut escaped, b| {
+                match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => escaped.push(b as char),
+                    b => escaped.push_str(&format!("%{:02X}", b)),
+                }
+                escaped
+            }),
+        }
+    }
+}
+
+fn yaml(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{:04X}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted
+}
+
+impl str::FromStr for Escape {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Escape::None),
+            "json" => Ok(Escape::Json),
+            "yaml" => Ok(Escape::Yaml),
+            "shell" => Ok(Escape::Shell),
+            "xml" | "html" => Ok(Escape::Xml),
+            "url" => Ok(Escape::Url),
+            _ => Err(String::from(r#"only "none", "json", "yaml", "shell", "xml", "html" and "url" are supported"#)),
+        }
+    }
+}
+impl fmt::Debug for Escape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Escape::None => write!(f, "none"),
+            Escape::Json => write!(f, "json"),
+            Escape::Yaml => write!(f, "yaml"),
+            Escape::Shell => write!(f, "shell"),
+            Escape::Xml => write!(f, "xml"),
+            Escape::Url => write!(f, "url"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::super::testing::{lock_env, translate_str};
+    use super::super::Delimiters;
+    use super::{Escape, EscapeMode};
+
+    const NASTY: &str = "p\"w: x' #$HOME `id` \\ & <b> 100%\n\tend";
+
+    /// renders `template` with `${env KAY_ESCAPE_TEST}` set to `value`
+    fn render(template: &str, escape: Escape, value: &str) -> String {
+        let _env = lock_env();
+        std::env::set_var("KAY_ESCAPE_TEST", value);
+        translate_str(template, Delimiters::default(), escape)
+            .unwrap_or_else(|_| panic!("translating {:?} failed", template))
+    }
+
+    #[test]
+    fn json_string() {
+        let rendered = render(r#"{"pw": "${env KAY_ESCAPE_TEST}"}"#, Escape::Json, NASTY);
+        let document: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(document["pw"], NASTY);
+    }
+
+    #[test]
+    fn yaml_double_quoted_scalar() {
+        let rendered = render("pw: \"${env KAY_ESCAPE_TEST}\"\nnext: 1\n", Escape::Yaml, NASTY);
+        let document: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(document["pw"].as_str(), Some(NASTY));
+        assert_eq!(document["next"].as_i64(), Some(1));
+    }
+
+    #[test]
+    fn shell_single_quoted_word() {
+        for value in [NASTY, "a; touch injected | cat", "* ?", "'", "it's", ""] {
+            let rendered = render("printf %s ${env KAY_ESCAPE_TEST}", Escape::Shell, value);
+            let output = Command::new("sh").arg("-c").arg(&rendered).output().unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), value, "{}", rendered);
+        }
+        assert_eq!(Escape::Shell.escape("it's"), r"'it'\''s'");
+        assert_eq!(Escape::Shell.escape(""), "''");
+    }
+
+    #[test]
+    fn xml_and_url() {
+        assert_eq!(Escape::Xml.escape(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+        assert_eq!(Escape::Url.escape("a b/c?d=é"), "a%20b%2Fc%3Fd%3D%C3%A9");
+    }
+
+    #[test]
+    fn raw_and_none() {
+        assert_eq!(render(r#""${env KAY_ESCAPE_TEST | raw}""#, Escape::Json, "a\"b"), "\"a\"b\"");
+        assert_eq!(render(r#""${env KAY_ESCAPE_TEST}""#, Escape::None, "a\"b"), "\"a\"b\"");
+    }
+
+    #[test]
+    fn inferred_only_from_known_extensions() {
+        assert_eq!(Escape::from_extension("config.json.tmpl"), Some(Escape::Json));
+        assert_eq!(Escape::from_extension("run.sh"), Some(Escape::Shell));
+        assert_eq!(Escape::from_extension("notes.txt"), None);
+        assert!(matches!("auto".parse(), Ok(EscapeMode::Auto)));
+        assert!(matches!("html".parse(), Ok(EscapeMode::Syntax(Escape::Xml))));
+        assert!("sql".parse::<EscapeMode>().is_err());
+    }
+}
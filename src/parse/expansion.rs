@@ -0,0 +1,216 @@
+use super::expr::unquote;
+use super::pattern::matches;
+use super::{ExprInternalError, Value};
+
+/// posix shell parameter expansion applied to an `env` or `var` lookup
+#[derive(Debug, Clone)]
+pub enum Expansion {
+    /// `NAME:-word`, the word when the value is unset or empty
+    Default(String),
+    /// `NAME:?message`, an error when the value is unset or empty
+    Error(String, String),
+    /// `NAME:+word`, the word when the value is set and not empty, nothing otherwise
+    Alternative(String),
+    /// `#NAME`, the length of the value
+    Length,
+    /// `NAME#pattern` / `NAME##pattern`, removes the shortest / longest matching prefix
+    RemovePrefix(String, bool),
+    /// `NAME%pattern` / `NAME%%pattern`, removes the shortest / longest matching suffix
+    RemoveSuffix(String, bool),
+    /// `NAME/pattern/replacement` / `NAME//pattern/replacement`, replaces the first / every match
+    Replace(String, String, bool),
+}
+
+/// offset of the first of `operators` that is not inside brackets or quotes,
+/// so that e.g. `$.items[0:2]` is a name and not an expansion
+fn find_operator(word: &str, operators: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    word.char_indices()
+        .find(|&(_, ch)| {
+            match (quote, ch) {
+                (Some(q), ch) if ch == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"') | (None, '\'') => quote = Some(ch),
+                (None, '[') | (None, '(') => depth += 1,
+                (None, ']') | (None, ')') => depth = depth.saturating_sub(1),
+                (None, ch) if depth == 0 && operators.contains(ch) => return true,
+                _ => {}
+            }
+            false
+        })
+        .map(|(i, _)| i)
+}
+
+/// splits the argument of `env` / `var` into the name and its expansion, if any
+pub fn split(word: &str) -> Result<(String, Option<Expansion>), String> {
+    if let Some(name) = word.strip_prefix('#').filter(|name| !name.is_empty()) {
+        return Ok((name.into(), Some(Expansion::Length)));
+    }
+    let (name, operator) = match find_operator(word, ":#%/") {
+        Some(0) => return Err(format!(r#"expected a name before "{}""#, word)),
+        Some(i) => word.split_at(i),
+        None => return Ok((word.into(), None)),
+    };
+    let expansion = if let Some(word) = operator.strip_prefix(":-") {
+        Expansion::Default(unquote(word).into())
+    } else if let Some(message) = operator.strip_prefix(":?") {
+        Expansion::Error(name.into(), unquote(message).into())
+    } else if let Some(word) = operator.strip_prefix(":+") {
+        Expansion::Alternative(unquote(word).into())
+    } else if let Some(pattern) = operator.strip_prefix("##") {
+        Expansion::RemovePrefix(unquote(pattern).into(), true)
+    } else if let Some(pattern) = operator.strip_prefix('#') {
+        Expansion::RemovePrefix(unquote(pattern).into(), false)
+    } else if let Some(pattern) = operator.strip_prefix("%%") {
+        Expansion::RemoveSuffix(unquote(pattern).into(), true)
+    } else if let Some(pattern) = operator.strip_prefix('%') {
+        Expansion::RemoveSuffix(unquote(pattern).into(), false)
+    } else if let Some(rest) = operator.strip_prefix('/') {
+        let (all, rest) = match rest.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let (pattern, replacement) = match find_operator(rest, "/") {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        Expansion::Replace(unquote(pattern).into(), unquote(replacement).into(), all)
+    } else {
+        return Err(format!(r#"unknown expansion "{}""#, operator));
+    };
+    Ok((name.into(), Some(expansion)))
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Str(s) => s.is_empty(),
+        Value::Bytes(b) => b.is_empty(),
+        _ => false,
+    }
+}
+
+/// offsets of every character boundary of `s`, including both ends
+fn boundaries(s: &str) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    s.char_indices().map(|(i, _)| i).chain(Some(s.len()))
+}
+
+fn replace(s: &str, pattern: &str, replacement: &str, all: bool) -> String {
+    let mut replaced = String::with_capacity(s.len());
+    let mut rest = s;
+    let mut done = false;
+    while let Some(ch) = rest.chars().next() {
+        if !done {
+            // the longest match starting here, an empty pattern matches nothing
+            if let Some(end) = boundaries(rest).rev().filter(|&i| i > 0).find(|&i| matches(pattern, &rest[..i])) {
+                replaced.push_str(replacement);
+                rest = &rest[end..];
+                done = !all;
+                continue;
+            }
+        }
+        replaced.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    replaced
+}
+
+impl Expansion {
+    /// whether an unset variable is expanded instead of being an error
+    pub fn allows_unset(&self) -> bool {
+        matches!(self, Expansion::Default(_) | Expansion::Error(..) | Expansion::Alternative(_))
+    }
+
+    /// expands a value, `None` when the variable is not set
+    pub fn apply(&self, value: Option<Value>) -> Result<Value, ExprInternalError> {
+        let value = match (self, value) {
+            (Expansion::Default(_), Some(value)) | (Expansion::Error(..), Some(value)) if !is_empty(&value) => {
+                return Ok(value)
+            }
+            (Expansion::Default(word), _) => return Ok(Value::Str(word.clone())),
+            (Expansion::Error(name, message), _) => {
+                return Err(ExprInternalError::ParameterError(name.clone(), message.clone()))
+            }
+            (Expansion::Alternative(word), Some(value)) if !is_empty(&value) => return Ok(Value::Str(word.clone())),
+            (Expansion::Alternative(_), _) => return Ok(Value::Str(String::new())),
+            (_, value) => value.unwrap_or(Value::Null),
+        };
+        let s = value.to_string();
+        Ok(match self {
+            Expansion::Length => Value::Int(match &value {
+                Value::List(l) => l.len(),
+                Value::Map(m) => m.len(),
+                _ => s.chars().count(),
+            } as i64),
+            Expansion::RemovePrefix(pattern, longest) => {
+                let mut ends = boundaries(&s).collect::<Vec<_>>();
+                if *longest {
+                    ends.reverse();
+                }
+                let end = ends.into_iter().find(|&i| matches(pattern, &s[..i])).unwrap_or(0);
+                Value::Str(s[end..].into())
+            }
+            Expansion::RemoveSuffix(pattern, longest) => {
+                let mut starts = boundaries(&s).collect::<Vec<_>>();
+                if !*longest {
+                    starts.reverse();
+                }
+                let start = starts.into_iter().find(|&i| matches(pattern, &s[i..])).unwrap_or(s.len());
+                Value::Str(s[..start].into())
+            }
+            Expansion::Replace(pattern, replacement, all) => Value::Str(replace(&s, pattern, replacement, *all)),
+            Expansion::Default(_) | Expansion::Error(..) | Expansion::Alternative(_) => unreachable!(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::expr::ExprError;
+    use super::super::testing::{lock_env, render, render_err};
+    use super::super::{ExprInternalError, TranslateError};
+    use super::{split, Expansion};
+
+    #[test]
+    fn splitting() {
+        assert!(matches!(split("NAME"), Ok((ref name, None)) if name == "NAME"));
+        assert!(matches!(split("#NAME"), Ok((ref name, Some(Expansion::Length))) if name == "NAME"));
+        assert!(matches!(split("$.items[0:2]"), Ok((ref name, None)) if name == "$.items[0:2]"));
+        assert!(matches!(split("NAME:-a b"), Ok((_, Some(Expansion::Default(ref word)))) if word == "a b"));
+        assert!(matches!(split("F##*/"), Ok((_, Some(Expansion::RemovePrefix(ref p, true)))) if p == "*/"));
+        assert!(split(":-x").is_err());
+    }
+
+    #[test]
+    fn defaults_and_alternatives() {
+        let _env = lock_env();
+        std::env::set_var("KAY_EXPANSION_SET", "value");
+        std::env::set_var("KAY_EXPANSION_EMPTY", "");
+        assert_eq!(render("${env KAY_EXPANSION_UNSET:-fallback}"), "fallback");
+        assert_eq!(render("${env KAY_EXPANSION_EMPTY:-fallback}"), "fallback");
+        assert_eq!(render("${env KAY_EXPANSION_SET:-fallback}"), "value");
+        assert_eq!(render("${env KAY_EXPANSION_SET:+other}"), "other");
+        assert_eq!(render("${env KAY_EXPANSION_UNSET:+other}"), "");
+        assert_eq!(render("${env #KAY_EXPANSION_SET}"), "5");
+        match render_err(r#"${env KAY_EXPANSION_UNSET:?"is required"}"#) {
+            TranslateError::Expr(ExprError::Internal(ExprInternalError::ParameterError(name, message))) => {
+                assert_eq!((name.as_str(), message.as_str()), ("KAY_EXPANSION_UNSET", "is required"));
+            }
+            _ => panic!("expected a parameter error"),
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let _env = lock_env();
+        std::env::set_var("KAY_EXPANSION_FILE", "/srv/app/archive.tar.gz");
+        assert_eq!(render("${env KAY_EXPANSION_FILE#*/}"), "srv/app/archive.tar.gz");
+        assert_eq!(render("${env KAY_EXPANSION_FILE##*/}"), "archive.tar.gz");
+        assert_eq!(render("${env KAY_EXPANSION_FILE%.*}"), "/srv/app/archive.tar");
+        assert_eq!(render("${env KAY_EXPANSION_FILE%%.*}"), "/srv/app/archive");
+        assert_eq!(render("${env KAY_EXPANSION_FILE/a/A}"), "/srv/App/archive.tar.gz");
+        assert_eq!(render("${env KAY_EXPANSION_FILE//a/A}"), "/srv/App/Archive.tAr.gz");
+        assert_eq!(render("${env KAY_EXPANSION_FILE#nomatch}"), "/srv/app/archive.tar.gz");
+    }
+}
@@ -1,13 +1,46 @@
 use std::io;
 use std::env::var_os as env_var;
+use std::path::PathBuf;
 
-use super::{Vars, VarsError};
+use super::parser::{self, BinOp, Expr};
+use super::{filter, include, macros, ops, Context, Escape, IncludeError, Scope, Value, VarsError};
+
+/// part of an expression an error refers to, offsets are in bytes
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
 
 #[derive(Debug)]
 pub enum ExprInternalError {
     UnexpectedEof,
     UnknownExpressionType,
     UnknownEnv(String),
+    EnvAccessDenied(String),
+    UnknownFilter(String),
+    InvalidFilterArgument(String),
+    FileRead(PathBuf, io::Error),
+    FileAccessDenied(PathBuf),
+    IncludeCycle(PathBuf),
+    InvalidSyntax(String),
+    InvalidDelimiters(String),
+    SyntaxError(String, Span),
+    UnexpectedEnd(String),
+    UnclosedBlock(String),
+    UnknownName(String),
+    UnknownMacro(String),
+    MacroArity(String, usize, usize),
+    RecursiveMacro(String),
+    /// `${for ...}` over a value that is not a list
+    NotIterable(&'static str),
+    /// `${env NAME:?message}` of a variable that is not set or empty
+    ParameterError(String, String),
+    InvalidOperands(&'static str, &'static str, &'static str, Span),
+    InvalidOperand(&'static str, &'static str, Span),
+    DivisionByZero(Span),
+    IntegerOverflow(Span),
 }
 
 pub enum ExprError {
@@ -15,6 +48,7 @@ pub enum ExprError {
     Input(io::Error),
     Output(io::Error),
     Internal(ExprInternalError),
+    Include(IncludeError),
 }
 
 impl From<VarsError> for ExprError {
@@ -28,51 +62,151 @@ impl From<ExprInternalError> for ExprError {
     }
 }
 
-enum ExprType {
-    Env,
-    Var,
+/// `${# ...}` tags are dropped from the output
+pub fn is_comment(tag: &str) -> bool {
+    tag.trim_start().starts_with('#')
 }
 
-pub fn translate_expr<R: Iterator<Item = io::Result<char>>, W: io::Write>(
-    input_chars: &mut R,
-    output: &mut W,
-    vars: &Box<dyn Vars>,
-) -> Result<(), ExprError> {
-    let mut s = String::new();
-    loop {
-        match input_chars.next() {
-            None => {
-                return Err(ExprInternalError::UnexpectedEof)?
-            }
-            Some(Err(e)) => return Err(ExprError::Input(e)),
-            Some(Ok(ch)) if ch == '}' => break,
-            Some(Ok(ch)) => s.push(ch),
+/// splits a tag into its leading keyword and the (trimmed) rest
+pub fn keyword(tag: &str) -> (&str, &str) {
+    let tag = tag.trim();
+    match tag.find(char::is_whitespace) {
+        Some(i) => (&tag[..i], tag[i..].trim()),
+        None => (tag, ""),
+    }
+}
+
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_') && !parser::is_keyword(s)
         }
+        _ => false,
     }
+}
 
-    if s.bytes().len() < 4 {
-        return Err(ExprInternalError::UnknownExpressionType)?;
+/// splits `s` on `separator` where it is not part of a quoted string or a bracketed expression
+pub fn split_top_level(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, ch) in s.char_indices() {
+        match (quote, ch) {
+            (Some(q), ch) if ch == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (None, '[') | (None, '(') => depth += 1,
+            (None, ']') | (None, ')') => depth = depth.saturating_sub(1),
+            (None, ch) if ch == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + ch.len_utf8();
+            }
+            _ => {}
+        }
     }
+    parts.push(&s[start..]);
+    parts
+}
 
-    let (expr_type, expr_path) = if &s[0..4] == "env " {
-        (ExprType::Env, &s[4..])
-    } else if &s[0..4] == "var " {
-        (ExprType::Var, &s[4..])
+/// strips the surrounding double quotes of a path argument, if present
+pub fn unquote(arg: &str) -> &str {
+    if arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"') {
+        &arg[1..arg.len() - 1]
     } else {
-        return Err(ExprInternalError::UnknownExpressionType)?;
-    };
-    match expr_type {
-        ExprType::Var => {
-            output.write(vars.get(expr_path)?.as_bytes()).map_err(ExprError::Output)?;
-            Ok(())
+        arg
+    }
+}
+
+pub fn env(name: &str) -> Option<Value> {
+    use std::os::unix::ffi::OsStringExt; // linux only for now ... sorry
+    env_var(name).map(|value| Value::from_bytes(value.into_vec()))
+}
+
+pub fn eval(expr: &Expr, ctx: &Context, scope: &mut Scope) -> Result<Value, ExprError> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Name(name) => match scope.local(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(ExprInternalError::UnknownName(name.clone()).into()),
         },
-        ExprType::Env => match env_var(expr_path) {
-            Some(value) => {
-                use std::os::unix::ffi::OsStrExt; // linux only for now ... sorry
-                output.write(value.as_bytes()).map_err(ExprError::Output)?;
-                Ok(())
+        Expr::Var(path) => {
+            let value = ctx.vars.get(path)?;
+            ctx.secrets.lookup(path, &value);
+            Ok(value)
+        }
+        Expr::Env(name) => {
+            let value = ctx.env.get(name)?.ok_or_else(|| ExprInternalError::UnknownEnv(name.clone()))?;
+            ctx.secrets.lookup(name, &value);
+            Ok(value)
+        }
+        Expr::Expand(expr, expansion) => {
+            let value = match eval(expr, ctx, scope) {
+                Ok(value) => Some(value),
+                Err(ExprError::Internal(ExprInternalError::UnknownEnv(_))) | Err(ExprError::Vars(VarsError::NotFound(_)))
+                    if expansion.allows_unset() =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+            Ok(expansion.apply(value)?)
+        }
+        Expr::File(path) => Ok(Value::from_bytes(ctx.files.read(path, &ctx.dir)?)),
+        Expr::Include(path) => include::include(path, ctx).map(Value::from_bytes),
+        Expr::Call(name, args) => macros::call(name, args, ctx, scope),
+        Expr::Not(expr) => Ok(Value::Bool(!eval(expr, ctx, scope)?.is_truthy())),
+        Expr::Neg(expr, span) => Ok(ops::negate(eval(expr, ctx, scope)?, span)?),
+        Expr::Binary(BinOp::And, left, right, _) => Ok(Value::Bool(
+            eval(left, ctx, scope)?.is_truthy() && eval(right, ctx, scope)?.is_truthy(),
+        )),
+        Expr::Binary(BinOp::Or, left, right, _) => Ok(Value::Bool(
+            eval(left, ctx, scope)?.is_truthy() || eval(right, ctx, scope)?.is_truthy(),
+        )),
+        Expr::Binary(op, left, right, span) => {
+            let left = eval(left, ctx, scope)?;
+            let right = eval(right, ctx, scope)?;
+            Ok(ops::binary(*op, left, right, span)?)
+        }
+        Expr::Filter(expr, name, args) => {
+            let value = filter::apply(name, args, eval(expr, ctx, scope)?)?;
+            if name == "secret" {
+                ctx.secrets.add(&value);
             }
-            None => Err(ExprInternalError::UnknownEnv(expr_path.into()))?,
-        },
+            Ok(value)
+        }
     }
 }
+
+/// parses and evaluates the source of an expression
+pub fn evaluate(expr: &str, ctx: &Context, scope: &mut Scope) -> Result<Value, ExprError> {
+    let expr = parser::parse(expr)?;
+    eval(&expr, ctx, scope)
+}
+
+/// rendered templates and values passed through `| raw` are written without escaping
+fn is_raw(expr: &Expr) -> bool {
+    match expr {
+        Expr::Filter(_, name, _) if name == "raw" => true,
+        Expr::Filter(expr, _, _) => is_raw(expr),
+        Expr::Include(_) | Expr::Call(..) => true,
+        _ => false,
+    }
+}
+
+pub fn translate_expr<W: io::Write>(
+    expr: &str,
+    output: &mut W,
+    ctx: &Context,
+    scope: &mut Scope,
+) -> Result<(), ExprError> {
+    let expr = parser::parse(expr)?;
+    let value = eval(&expr, ctx, scope)?;
+    let value = if ctx.escape == Escape::None || is_raw(&expr) {
+        value.into_bytes()
+    } else {
+        ctx.escape.escape(&value.to_string()).into_bytes()
+    };
+    output.write_all(&value).map_err(ExprError::Output)
+}
@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::ExprInternalError;
+
+pub struct FilePolicy {
+    base_dir: Option<PathBuf>,
+    allowed: Vec<PathBuf>,
+    /// directory of the rendered template, included templates may always be read from it
+    template_dir: PathBuf,
+    /// every file templates tried to read, watched by `--watch`
+    accessed: RefCell<Vec<PathBuf>>,
+}
+
+impl FilePolicy {
+    /// `base_dir` overrides the directory `${file ...}` paths are resolved from (the template's by default),
+    /// `allowed` lists the directories templates may read from
+    pub fn new(base_dir: Option<PathBuf>, allowed: Vec<PathBuf>, template_dir: PathBuf) -> Self {
+        FilePolicy {
+            base_dir,
+            allowed,
+            template_dir,
+            accessed: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn accessed(&self) -> Vec<PathBuf> {
+        self.accessed.borrow().clone()
+    }
+
+    /// returns the canonical form of `path` if it lies within one of the allowed directories
+    pub fn check(&self, path: &Path) -> Result<PathBuf, ExprInternalError> {
+        self.check_within(path, &self.allowed)
+    }
+
+    /// like `check` for `${include}` and `${import}`, which may also read the template's directory
+    pub fn check_template(&self, path: &Path) -> Result<PathBuf, ExprInternalError> {
+        self.check_within(path, self.allowed.iter().chain(Some(&self.template_dir)))
+    }
+
+    fn check_within<'d, I: IntoIterator<Item = &'d PathBuf>>(
+        &self,
+        path: &Path,
+        dirs: I,
+    ) -> Result<PathBuf, ExprInternalError> {
+        self.accessed.borrow_mut().push(path.into());
+        let real_path = path
+            .canonicalize()
+            .map_err(|e| ExprInternalError::FileRead(path.into(), e))?;
+        let allowed = dirs
+            .into_iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| real_path.starts_with(dir));
+        if allowed {
+            Ok(real_path)
+        } else {
+            Err(ExprInternalError::FileAccessDenied(path.into()))
+        }
+    }
+
+    pub fn read(&self, path: &str, template_dir: &Path) -> Result<Vec<u8>, ExprInternalError> {
+        let path = self.base_dir.as_deref().unwrap_or(template_dir).join(path);
+        let real_path = self.check(&path)?;
+        let mut buffer = Vec::new();
+        fs::File::open(&real_path)
+            .and_then(|mut file| file.read_to_end(&mut buffer))
+            .map_err(|e| ExprInternalError::FileRead(path, e))?;
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    use super::super::testing::temp_dir;
+    use super::{ExprInternalError, FilePolicy};
+
+    /// `base` holding `a`, `sub/b` and links into `other`, which holds `secret`
+    fn files(name: &str) -> (PathBuf, PathBuf) {
+        let dir = temp_dir(name);
+        let (base, other) = (dir.join("base"), dir.join("other"));
+        fs::create_dir_all(base.join("sub")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        fs::write(base.join("a"), "a").unwrap();
+        fs::write(base.join("sub/b"), "b").unwrap();
+        fs::write(other.join("secret"), "secret").unwrap();
+        symlink("../other/secret", base.join("link")).unwrap();
+        symlink("../other", base.join("linkdir")).unwrap();
+        (base, other)
+    }
+
+    fn denied(result: Result<Vec<u8>, ExprInternalError>) -> bool {
+        matches!(result, Err(ExprInternalError::FileAccessDenied(_)))
+    }
+
+    #[test]
+    fn reads_within_the_base_dir() {
+        let (base, _) = files("file-within");
+        let policy = FilePolicy::new(None, vec![base.clone()], base.clone());
+        assert_eq!(policy.read("a", &base).unwrap(), b"a");
+        assert_eq!(policy.read("sub/b", &base).unwrap(), b"b");
+        assert_eq!(policy.read("sub/../a", &base).unwrap(), b"a");
+        assert_eq!(policy.read("../a", &base.join("sub")).unwrap(), b"a", "relative to the template");
+        assert!(matches!(policy.read("missing", &base), Err(ExprInternalError::FileRead(_, _))));
+        let accessed = ["a", "sub/b", "sub/../a", "sub/../a", "missing"].iter().map(|path| base.join(path));
+        assert_eq!(policy.accessed(), accessed.collect::<Vec<_>>(), "every file tried is watched");
+    }
+
+    #[test]
+    fn escapes_are_denied() {
+        let (base, other) = files("file-escapes");
+        let policy = FilePolicy::new(None, vec![base.clone()], base.clone());
+        assert!(denied(policy.read("../other/secret", &base)));
+        assert!(denied(policy.read("sub/../../other/secret", &base)));
+        assert!(denied(policy.read(other.join("secret").to_str().unwrap(), &base)));
+        assert!(denied(policy.read("link", &base)));
+        assert!(denied(policy.read("linkdir/secret", &base)));
+    }
+
+    #[test]
+    fn allowed_dirs() {
+        let (base, other) = files("file-allowed");
+        // `--file-base-dir base --file-allow other`
+        let policy = FilePolicy::new(Some(base.clone()), vec![base.clone(), other.clone()], base.join("sub"));
+        assert_eq!(policy.read("a", &base.join("sub")).unwrap(), b"a", "resolved from the base dir");
+        assert_eq!(policy.read("link", &base).unwrap(), b"secret");
+        assert_eq!(policy.read("../other/secret", &base).unwrap(), b"secret");
+        // a directory that only shares the prefix of an allowed one is not allowed
+        fs::create_dir_all(base.with_file_name("base2")).unwrap();
+        fs::write(base.with_file_name("base2/c"), "c").unwrap();
+        assert!(denied(policy.read("../base2/c", &base)));
+        // includes may read the template directory, files may not
+        let policy = FilePolicy::new(None, vec![other.clone()], base.clone());
+        assert!(matches!(policy.check(&base.join("a")), Err(ExprInternalError::FileAccessDenied(_))));
+        assert!(policy.check_template(&base.join("a")).is_ok());
+        assert!(policy.check_template(&other.join("secret")).is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+use super::{ExprInternalError, Value};
+
+/// applies a single `| name args...` filter to the result of an expression
+pub fn apply(name: &str, args: &[Value], value: Value) -> Result<Value, ExprInternalError> {
+    match (name, args) {
+        ("trim", []) => Ok(Value::from_bytes(trim(&value.into_bytes()).to_vec())),
+        ("indent", [Value::Int(width)]) if *width >= 0 => {
+            Ok(Value::from_bytes(indent(&value.into_bytes(), *width as usize)))
+        }
+        ("base64", []) => Ok(Value::Str(base64::encode(&value.into_bytes()))),
+        ("upper", []) => Ok(Value::Str(value.to_string().to_uppercase())),
+        ("lower", []) => Ok(Value::Str(value.to_string().to_lowercase())),
+        // only marks the expression, see `expr::is_raw`
+        ("raw", []) => Ok(value),
+        // only marks the value, see `Secrets`
+        ("secret", []) => Ok(value),
+        ("trim", _) | ("indent", _) | ("base64", _) | ("upper", _) | ("lower", _) | ("raw", _) | ("secret", _) => {
+            Err(ExprInternalError::InvalidFilterArgument(name.into()))
+        }
+        _ => Err(ExprInternalError::UnknownFilter(name.into())),
+    }
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    &value[start..end]
+}
+
+/// prefixes every line with `width` spaces, a trailing newline does not start a new line
+fn indent(value: &[u8], width: usize) -> Vec<u8> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut line_start = true;
+    for &b in value {
+        if line_start {
+            result.resize(result.len() + width, b' ');
+        }
+        result.push(b);
+        line_start = b == b'\n';
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, ExprInternalError, Value};
+
+    fn filter(name: &str, args: &[Value], value: &str) -> String {
+        apply(name, args, Value::Str(value.into())).unwrap().to_string()
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(filter("trim", &[], " \n a b\t\n"), "a b");
+        assert_eq!(filter("trim", &[], " \n "), "");
+        assert_eq!(filter("indent", &[Value::Int(2)], "a\n\nb\n"), "  a\n  \n  b\n");
+        assert_eq!(filter("indent", &[Value::Int(0)], "a\nb"), "a\nb");
+        assert_eq!(filter("base64", &[], "hi"), "aGk=");
+        assert_eq!(filter("upper", &[], "straße"), "STRASSE");
+        assert_eq!(filter("lower", &[], "ABC"), "abc");
+        assert_eq!(filter("raw", &[], "<a>"), "<a>");
+        assert_eq!(filter("secret", &[], "pw"), "pw");
+        assert_eq!(apply("upper", &[], Value::Int(1)).unwrap(), Value::Str("1".into()));
+        assert_eq!(apply("trim", &[], Value::Bytes(vec![b' ', 0xff, b' '])).unwrap(), Value::Bytes(vec![0xff]));
+        assert_eq!(apply("base64", &[], Value::Bytes(vec![0xff])).unwrap(), Value::Str("/w==".into()));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        let invalid = |name: &str, args: &[Value]| {
+            matches!(apply(name, args, Value::Null), Err(ExprInternalError::InvalidFilterArgument(ref n)) if n == name)
+        };
+        assert!(invalid("indent", &[]));
+        assert!(invalid("indent", &[Value::Int(-1)]));
+        assert!(invalid("indent", &[Value::Str("2".into())]));
+        assert!(invalid("trim", &[Value::Int(1)]));
+        assert!(invalid("secret", &[Value::Null]));
+        assert!(matches!(apply("nope", &[], Value::Null), Err(ExprInternalError::UnknownFilter(_))));
+    }
+}
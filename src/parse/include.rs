@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::PathBuf;
+
+use super::{translate, Context, ExprError, ExprInternalError, PositionIterator, ReadChars, Scope, TranslateError};
+
+/// an error that happened while translating an included template,
+/// `line` and `col` point into the included file
+pub struct IncludeError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+    pub error: Box<TranslateError>,
+}
+
+/// translates the template at `path` (relative to the including template) with the same vars
+pub fn include(path: &str, ctx: &Context) -> Result<Vec<u8>, ExprError> {
+    let mut output = Vec::new();
+    translate_file(path, ctx, &mut Scope::new(), &mut output)?;
+    Ok(output)
+}
+
+/// translates the library at `path` (relative to the importing template) discarding its output,
+/// the macros it defines become available in `scope`
+pub fn import(path: &str, ctx: &Context, scope: &mut Scope) -> Result<(), ExprError> {
+    let mut library = Scope::new();
+    translate_file(path, ctx, &mut library, &mut io::sink())?;
+    scope.import(library);
+    Ok(())
+}
+
+fn translate_file<W: Write>(path: &str, ctx: &Context, scope: &mut Scope, output: &mut W) -> Result<(), ExprError> {
+    let path = ctx.dir.join(path);
+    let real_path = ctx.files.check_template(&path)?;
+    if ctx.ancestors().any(|c| c.template.as_ref() == Some(&real_path)) {
+        return Err(ExprInternalError::IncludeCycle(path).into());
+    }
+    let file = File::open(&real_path).map_err(|e| ExprInternalError::FileRead(path.clone(), e))?;
+    let mut reader = BufReader::new(file);
+    let mut input_chars = PositionIterator::from(reader.chars());
+    let nested = Context {
+        dir: path.parent().map(PathBuf::from).unwrap_or_default(),
+        template: Some(real_path),
+        delimiters: ctx.delimiters.clone(),
+        parent: Some(ctx),
+        ..*ctx
+    };
+
+    match translate(&mut input_chars, output, &nested, scope) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            let error = match error {
+                TranslateError::Input(e) => TranslateError::Expr(ExprInternalError::FileRead(path.clone(), e).into()),
+                error => error,
+            };
+            Err(ExprError::Include(IncludeError {
+                path,
+                line: input_chars.line(),
+                col: input_chars.col(),
+                error: Box::new(error),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::super::testing::{render_file, temp_dir};
+    use super::{ExprError, ExprInternalError, TranslateError};
+
+    fn templates(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = temp_dir(name);
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    /// the innermost error and the includes it happened in, relative to `dir`
+    fn include_error(dir: &Path, template: &str) -> (ExprInternalError, Vec<(String, usize, usize)>) {
+        let (error, includes) = match render_file(&dir.join(template)) {
+            Ok(output) => panic!("{} gave {:?}", template, output),
+            Err(error) => error.unwind_includes(),
+        };
+        let includes = includes
+            .into_iter()
+            .map(|(path, line, col)| (path.strip_prefix(dir).unwrap().display().to_string(), line, col))
+            .collect();
+        match error {
+            TranslateError::Expr(ExprError::Internal(error)) => (error, includes),
+            _ => panic!("{} did not fail in an expression", template),
+        }
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_template() {
+        let dir = templates(
+            "include-relative",
+            &[
+                ("main.tmpl", r#"a ${include "sub/b.tmpl"}"#),
+                ("sub/b.tmpl", r#"b ${include "c.tmpl"}"#),
+                ("sub/c.tmpl", "c"),
+                ("c.tmpl", "wrong"),
+            ],
+        );
+        assert_eq!(render_file(&dir.join("main.tmpl")).ok().unwrap(), "a b c");
+    }
+
+    #[test]
+    fn cycles() {
+        let dir = templates(
+            "include-cycles",
+            &[
+                ("self.tmpl", r#"${include "self.tmpl"}"#),
+                ("a.tmpl", "a\n${include \"sub/b.tmpl\"}"),
+                ("sub/b.tmpl", r#"${include "../a.tmpl"}"#),
+            ],
+        );
+        match include_error(&dir, "self.tmpl") {
+            (ExprInternalError::IncludeCycle(path), includes) => {
+                assert_eq!(path, dir.join("self.tmpl"));
+                assert!(includes.is_empty());
+            }
+            (error, _) => panic!("{:?}", error),
+        }
+        match include_error(&dir, "a.tmpl") {
+            (ExprInternalError::IncludeCycle(path), includes) => {
+                assert_eq!(path, dir.join("sub/../a.tmpl"));
+                assert_eq!(includes, [(String::from("sub/b.tmpl"), 1, 23)]);
+            }
+            (error, _) => panic!("{:?}", error),
+        }
+    }
+
+    #[test]
+    fn errors_report_the_include_chain() {
+        let dir = templates(
+            "include-chain",
+            &[
+                ("main.tmpl", "main\n  ${include \"sub/b.tmpl\"}\n"),
+                ("sub/b.tmpl", r#"${include "c.tmpl"}"#),
+                ("sub/c.tmpl", "c\n\nc ${nope}"),
+            ],
+        );
+        match include_error(&dir, "main.tmpl") {
+            (ExprInternalError::UnknownName(name), includes) => {
+                assert_eq!(name, "nope");
+                assert_eq!(includes, [(String::from("sub/b.tmpl"), 1, 20), (String::from("sub/c.tmpl"), 3, 10)]);
+            }
+            (error, _) => panic!("{:?}", error),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::{ExprInternalError, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Op(&'static str),
+    End,
+}
+
+/// longest operators first so that `<=` is not read as `<`
+const OPERATORS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", "|",
+];
+
+/// splits an expression into tokens, the parser pulls them one at a time
+/// because arguments of `var`, `env`, `file` and `include` are not made of tokens
+pub struct Lexer<'s> {
+    src: &'s str,
+    pos: usize,
+}
+
+impl<'s> Lexer<'s> {
+    pub fn new(src: &'s str) -> Self {
+        Lexer { src, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            text: self.src[start..end].into(),
+        }
+    }
+
+    pub fn error(&self, message: &str, start: usize, end: usize) -> ExprInternalError {
+        ExprInternalError::SyntaxError(message.into(), self.span(start, end))
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'s str {
+        let rest = self.rest();
+        let len = rest.find(|ch| !f(ch)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// reads a double quoted string, `\` escapes the following character
+    fn string(&mut self, start: usize) -> Result<String, ExprInternalError> {
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, ch)) => value.push(ch),
+                    None => break,
+                },
+                ch => value.push(ch),
+            }
+        }
+        Err(self.error("unterminated string", start, self.src.len()))
+    }
+
+    /// returns the next token with its start and end offsets
+    pub fn next_token(&mut self) -> Result<(Tok, usize, usize), ExprInternalError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let rest = self.rest();
+        let tok = match rest.chars().next() {
+            None => Tok::End,
+            Some('"') => Tok::Str(self.string(start)?),
+            Some(ch) if ch.is_ascii_digit() => {
+                let int = self.take_while(|ch| ch.is_ascii_digit());
+                if self.rest().starts_with('.') && self.rest()[1..].starts_with(|ch: char| ch.is_ascii_digit()) {
+                    self.pos += 1;
+                    self.take_while(|ch| ch.is_ascii_digit());
+                    let number = &self.src[start..self.pos];
+                    Tok::Float(number.parse().map_err(|_| self.error("invalid number", start, self.pos))?)
+                } else {
+                    Tok::Int(int.parse().map_err(|_| self.error("number too large", start, self.pos))?)
+                }
+            }
+            Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+                Tok::Ident(self.take_while(|ch| ch.is_ascii_alphanumeric() || ch == '_').into())
+            }
+            Some(_) => match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    self.pos += op.len();
+                    Tok::Op(op)
+                }
+                None => {
+                    let end = start + rest.chars().next().map_or(0, char::len_utf8);
+                    return Err(self.error("unexpected character", start, end));
+                }
+            },
+        };
+        Ok((tok, start, self.pos))
+    }
+
+    /// reads a quoted string or a bare word such as a jsonpath selector,
+    /// a bare word ends with whitespace, `)`, `,` or `|` that are not enclosed in brackets or quotes
+    pub fn argument(&mut self, what: &str) -> Result<String, ExprInternalError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.rest().starts_with('"') {
+            return self.string(start);
+        }
+        let mut depth = 0usize;
+        let mut quote = None;
+        let rest = self.rest();
+        let len = rest
+            .char_indices()
+            .find(|&(_, ch)| {
+                match (quote, ch) {
+                    (Some(q), ch) if ch == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '"') | (None, '\'') => quote = Some(ch),
+                    (None, '[') | (None, '(') => depth += 1,
+                    (None, ']') | (None, ')') if depth > 0 => depth -= 1,
+                    (None, ')') | (None, ',') | (None, '|') if depth == 0 => return true,
+                    (None, ch) if ch.is_whitespace() && depth == 0 => return true,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        if len == 0 {
+            return Err(self.error(&format!("expected {}", what), start, start));
+        }
+        self.pos += len;
+        Ok(rest[..len].into())
+    }
+}
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use super::expr::{eval, is_identifier, split_top_level};
+use super::parser::Expr;
+use super::scanner::Token;
+use super::{translate_tokens, Context, ExprError, ExprInternalError, Scope, Value};
+
+/// a named snippet created by `${define name(arg1, arg2)}...${end}`
+pub struct Macro {
+    pub name: String,
+    params: Vec<String>,
+    body: Vec<Token>,
+    /// directory of the defining template, includes in the body are resolved from here
+    dir: PathBuf,
+    /// canonical path of the defining template
+    template: Option<PathBuf>,
+}
+
+/// splits `name(a, b)` into the name and the unparsed arguments, the parentheses are optional
+fn parse_signature(signature: &str) -> Option<(&str, Vec<&str>)> {
+    let signature = signature.trim();
+    let (name, args) = match signature.find('(') {
+        Some(i) if signature.ends_with(')') => {
+            let args = signature[i + 1..signature.len() - 1].trim();
+            let args = if args.is_empty() {
+                Vec::new()
+            } else {
+                split_top_level(args, ',').into_iter().map(str::trim).collect()
+            };
+            (signature[..i].trim(), args)
+        }
+        Some(_) => return None,
+        None => (signature, Vec::new()),
+    };
+    if is_identifier(name) {
+        Some((name, args))
+    } else {
+        None
+    }
+}
+
+impl Macro {
+    /// a macro defined by the template translated with `ctx`
+    pub fn parse(signature: &str, body: Vec<Token>, ctx: &Context) -> Result<Macro, ExprInternalError> {
+        let invalid = || ExprInternalError::InvalidSyntax(format!("define {}", signature.trim()));
+        let (name, params) = parse_signature(signature).ok_or_else(invalid)?;
+        if !params.iter().all(|param| is_identifier(param)) {
+            return Err(invalid());
+        }
+        Ok(Macro {
+            name: name.into(),
+            params: params.into_iter().map(String::from).collect(),
+            body,
+            dir: ctx.dir.clone(),
+            template: ctx.template.clone(),
+        })
+    }
+}
+
+/// translates the body of macro `name`, binding the arguments to its parameters,
+/// paths in the body are relative to the template that defined it
+pub fn call(name: &str, args: &[Expr], ctx: &Context, scope: &mut Scope) -> Result<Value, ExprError> {
+    let definition = scope
+        .get_macro(name)
+        .ok_or_else(|| ExprInternalError::UnknownMacro(name.into()))?;
+    if args.len() != definition.params.len() {
+        return Err(ExprInternalError::MacroArity(name.into(), definition.params.len(), args.len()).into());
+    }
+    if scope.is_calling(name) {
+        return Err(ExprInternalError::RecursiveMacro(name.into()).into());
+    }
+    let values = args
+        .iter()
+        .map(|arg| eval(arg, ctx, scope))
+        .collect::<Result<Vec<_>, _>>()?;
+    let locals = definition.params.iter().cloned().zip(values).collect();
+
+    let definition_ctx = Context {
+        dir: definition.dir.clone(),
+        template: definition.template.clone(),
+        delimiters: ctx.delimiters.clone(),
+        parent: Some(ctx),
+        ..*ctx
+    };
+    scope.push_call(name.into(), locals);
+    let mut output = Vec::new();
+    let result = translate_tokens(&mut definition.body.iter().cloned().map(Ok), &mut output, &definition_ctx, scope);
+    scope.pop_call();
+    result?;
+    Ok(Value::from_bytes(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::super::testing::{render, render_err, render_file, temp_dir};
+    use super::super::{ExprError, TranslateError};
+    use super::ExprInternalError;
+
+    fn expr_error(template: &str) -> ExprInternalError {
+        match render_err(template) {
+            TranslateError::Expr(ExprError::Internal(error)) => error,
+            _ => panic!("{:?} did not fail in an expression", template),
+        }
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(render("${define greet(name)}hello ${name}${end}${call greet(\"you\")}"), "hello you");
+        assert_eq!(render("${define two}2${end}${call two()}${call two()}"), "22");
+        assert_eq!(render("${define m(a, b)}${a}-${b}${end}${call m(1 + 1, \"x\" | upper)}"), "2-X");
+        assert!(matches!(expr_error("${define bad(1)}${end}"), ExprInternalError::InvalidSyntax(_)));
+        assert!(matches!(expr_error("${call nope()}"), ExprInternalError::UnknownMacro(ref name) if name == "nope"));
+    }
+
+    #[test]
+    fn arity() {
+        let error = expr_error("${define m(a, b)}${end}${call m(1)}");
+        assert!(matches!(error, ExprInternalError::MacroArity(ref name, 2, 1) if name == "m"));
+        assert!(matches!(expr_error("${define m()}${end}${call m(1)}"), ExprInternalError::MacroArity(_, 0, 1)));
+    }
+
+    #[test]
+    fn recursion() {
+        let error = expr_error("${define m()}${call m()}${end}${call m()}");
+        assert!(matches!(error, ExprInternalError::RecursiveMacro(ref name) if name == "m"));
+        let error = expr_error("${define a()}${call b()}${end}${define b()}${call a()}${end}${call a()}");
+        assert!(matches!(error, ExprInternalError::RecursiveMacro(ref name) if name == "a"));
+        // calling the same macro twice in a row is not recursion
+        assert_eq!(render("${define m(x)}${x}${end}${call m(call m(1))}"), "1");
+    }
+
+    #[test]
+    fn frames() {
+        let macros = "${let t = \"template\"}${define inner()}${t}${end}${define outer(p)}${let o = p}${call inner()}${end}";
+        // a call sees its own frame and the template's
+        assert_eq!(render(&format!("{}${{call outer(1)}}", macros)), "template");
+        // but not the frame of its caller
+        let template = format!("{}${{define peek()}}${{p}}${{end}}${{define caller(p)}}${{call peek()}}${{end}}${{call caller(1)}}", macros);
+        assert!(matches!(expr_error(&template), ExprInternalError::UnknownName(ref name) if name == "p"));
+        // and its bindings end with it
+        let template = format!("{}${{call outer(1)}}${{o}}", macros);
+        assert!(matches!(expr_error(&template), ExprInternalError::UnknownName(ref name) if name == "o"));
+    }
+
+    #[test]
+    fn imported_macros_resolve_paths_from_their_library() {
+        let dir = temp_dir("macros-import");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.tmpl"), r#"${import "lib/lib.tmpl"}${call header()}"#).unwrap();
+        fs::write(dir.join("lib/lib.tmpl"), r#"${define header()}${include "header.tmpl"} ${file "footer.txt"}${end}"#).unwrap();
+        fs::write(dir.join("lib/header.tmpl"), "header").unwrap();
+        fs::write(dir.join("lib/footer.txt"), "footer").unwrap();
+        fs::write(dir.join("header.tmpl"), "wrong").unwrap();
+        fs::write(dir.join("footer.txt"), "wrong").unwrap();
+        assert_eq!(render_file(&dir.join("main.tmpl")).ok().unwrap(), "header footer");
+    }
+}
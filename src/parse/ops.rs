@@ -0,0 +1,192 @@
+use std::cmp::Ordering;
+
+use super::parser::BinOp;
+use super::{ExprInternalError, Span, Value};
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+}
+
+/// numbers, and strings holding a number such as the value of `${env PORT}`
+fn number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Int(i) => Some(Number::Int(*i)),
+        Value::Float(f) => Some(Number::Float(*f)),
+        Value::Str(s) => {
+            let s = s.trim();
+            s.parse()
+                .map(Number::Int)
+                .ok()
+                .or_else(|| s.parse().ok().filter(|f: &f64| f.is_finite()).map(Number::Float))
+        }
+        _ => None,
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::Float(_))
+}
+
+fn invalid_operands(op: BinOp, left: &Value, right: &Value, span: &Span) -> ExprInternalError {
+    ExprInternalError::InvalidOperands(op.symbol(), left.type_name(), right.type_name(), span.clone())
+}
+
+fn arithmetic(op: BinOp, left: Number, right: Number, span: &Span) -> Result<Value, ExprInternalError> {
+    let overflow = || ExprInternalError::IntegerOverflow(span.clone());
+    let division_by_zero = || ExprInternalError::DivisionByZero(span.clone());
+    match (left, right) {
+        (Number::Int(l), Number::Int(r)) => match op {
+            BinOp::Add => l.checked_add(r).map(Value::Int).ok_or_else(overflow),
+            BinOp::Sub => l.checked_sub(r).map(Value::Int).ok_or_else(overflow),
+            BinOp::Mul => l.checked_mul(r).map(Value::Int).ok_or_else(overflow),
+            BinOp::Div | BinOp::Rem if r == 0 => Err(division_by_zero()),
+            // stays an integer when the division is exact, `i64::MIN / -1` is exact but overflows
+            BinOp::Div => match l.checked_rem(r) {
+                Some(0) | None => l.checked_div(r).map(Value::Int).ok_or_else(overflow),
+                Some(_) => Ok(Value::Float(l as f64 / r as f64)),
+            },
+            BinOp::Rem => l.checked_rem(r).map(Value::Int).ok_or_else(overflow),
+            _ => unreachable!(),
+        },
+        (left, right) => {
+            let (l, r) = (left.as_f64(), right.as_f64());
+            match op {
+                BinOp::Add => Ok(Value::Float(l + r)),
+                BinOp::Sub => Ok(Value::Float(l - r)),
+                BinOp::Mul => Ok(Value::Float(l * r)),
+                BinOp::Div | BinOp::Rem if r == 0.0 => Err(division_by_zero()),
+                BinOp::Div => Ok(Value::Float(l / r)),
+                BinOp::Rem => Ok(Value::Float(l % r)),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// numbers compare numerically with numbers and numeric strings, everything else must have the same type
+fn compare(op: BinOp, left: &Value, right: &Value, span: &Span) -> Result<Ordering, ExprInternalError> {
+    if is_number(left) || is_number(right) {
+        if let (Some(l), Some(r)) = (number(left), number(right)) {
+            return l
+                .as_f64()
+                .partial_cmp(&r.as_f64())
+                .ok_or_else(|| invalid_operands(op, left, right, span));
+        }
+    }
+    match (left, right) {
+        (Value::Str(l), Value::Str(r)) => Ok(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Ok(l.cmp(r)),
+        _ => Err(invalid_operands(op, left, right, span)),
+    }
+}
+
+fn equals(left: &Value, right: &Value) -> bool {
+    if is_number(left) || is_number(right) {
+        if let (Some(l), Some(r)) = (number(left), number(right)) {
+            return l.as_f64() == r.as_f64();
+        }
+    }
+    left == right
+}
+
+/// applies every operator except the short-circuiting `and` / `or`
+pub fn binary(op: BinOp, left: Value, right: Value, span: &Span) -> Result<Value, ExprInternalError> {
+    match op {
+        BinOp::Eq => Ok(Value::Bool(equals(&left, &right))),
+        BinOp::Ne => Ok(Value::Bool(!equals(&left, &right))),
+        BinOp::Lt => Ok(Value::Bool(compare(op, &left, &right, span)? == Ordering::Less)),
+        BinOp::Le => Ok(Value::Bool(compare(op, &left, &right, span)? != Ordering::Greater)),
+        BinOp::Gt => Ok(Value::Bool(compare(op, &left, &right, span)? == Ordering::Greater)),
+        BinOp::Ge => Ok(Value::Bool(compare(op, &left, &right, span)? != Ordering::Less)),
+        BinOp::Add => match (left, right) {
+            (Value::Str(l), Value::Str(r)) => Ok(Value::Str(l + &r)),
+            (left, right) => match (number(&left), number(&right)) {
+                (Some(l), Some(r)) => arithmetic(op, l, r, span),
+                _ => Err(invalid_operands(op, &left, &right, span)),
+            },
+        },
+        BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => match (number(&left), number(&right)) {
+            (Some(l), Some(r)) => arithmetic(op, l, r, span),
+            _ => Err(invalid_operands(op, &left, &right, span)),
+        },
+        BinOp::And | BinOp::Or => unreachable!(),
+    }
+}
+
+pub fn negate(value: Value, span: &Span) -> Result<Value, ExprInternalError> {
+    match number(&value) {
+        Some(Number::Int(i)) => i
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| ExprInternalError::IntegerOverflow(span.clone())),
+        Some(Number::Float(f)) => Ok(Value::Float(-f)),
+        None => Err(ExprInternalError::InvalidOperand("-", value.type_name(), span.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::expr::ExprError;
+    use super::super::testing::{render, render_err};
+    use super::super::TranslateError;
+    use super::ExprInternalError;
+
+    fn internal_error(template: &str) -> ExprInternalError {
+        match render_err(template) {
+            TranslateError::Expr(ExprError::Internal(error)) => error,
+            _ => panic!("{:?} did not fail while evaluating", template),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(render("${1 + 2 * 3}"), "7");
+        assert_eq!(render("${(1 + 2) * 3}"), "9");
+        assert_eq!(render("${7 - 10}"), "-3");
+        assert_eq!(render("${7 % 4}"), "3");
+        assert_eq!(render("${6 / 3}"), "2");
+        assert_eq!(render("${7 / 2}"), "3.5");
+        assert_eq!(render("${0.5 + 1}"), "1.5");
+        assert_eq!(render(r#"${"8080" + 1}"#), "8081");
+        assert_eq!(render(r#"${"a" + "b"}"#), "ab");
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        assert!(matches!(internal_error("${1 / 0}"), ExprInternalError::DivisionByZero(_)));
+        assert!(matches!(internal_error("${1 % 0}"), ExprInternalError::DivisionByZero(_)));
+        assert!(matches!(internal_error("${9223372036854775807 + 1}"), ExprInternalError::IntegerOverflow(_)));
+        assert!(matches!(
+            internal_error("${(-9223372036854775807 - 1) / -1}"),
+            ExprInternalError::IntegerOverflow(_)
+        ));
+        assert!(matches!(
+            internal_error("${(-9223372036854775807 - 1) % -1}"),
+            ExprInternalError::IntegerOverflow(_)
+        ));
+        assert!(matches!(internal_error(r#"${"a" - 1}"#), ExprInternalError::InvalidOperands("-", "string", "int", _)));
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(render("${2 < 10}"), "true");
+        assert_eq!(render(r#"${"2" < 10}"#), "true");
+        assert_eq!(render(r#"${"2" < "10"}"#), "false");
+        assert_eq!(render("${1 == 1.0}"), "true");
+        assert_eq!(render(r#"${1 != "x"}"#), "true");
+        assert_eq!(render("${not false and 1 >= 1}"), "true");
+        assert_eq!(render("${false or null}"), "false");
+        assert!(matches!(internal_error(r#"${true < "x"}"#), ExprInternalError::InvalidOperands("<", "bool", "string", _)));
+    }
+}
@@ -0,0 +1,358 @@
+use super::expansion::{self, Expansion};
+use super::lexer::{Lexer, Tok};
+use super::{ExprInternalError, Span, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Or => "or",
+            BinOp::And => "and",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(Value),
+    Name(String),
+    Env(String),
+    Var(String),
+    File(String),
+    Include(String),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>, Span),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Span),
+    Filter(Box<Expr>, String, Vec<Value>),
+    /// an `env` or `var` lookup with a shell parameter expansion such as `NAME:-default`
+    Expand(Box<Expr>, Expansion),
+}
+
+const KEYWORDS: [&str; 15] = [
+    "and", "or", "not", "true", "false", "null", "env", "var", "file", "include", "call", "if", "else", "for", "in",
+];
+
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+/// recursive descent parser, from the loosest binding:
+/// `|` filters, `or`, `and`, `not`, comparisons, `+ -`, `* / %`, unary `-`
+struct Parser<'s> {
+    lexer: Lexer<'s>,
+    peeked: Option<(Tok, usize, usize)>,
+    last_end: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&mut self) -> Result<&Tok, ExprInternalError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next_token()?);
+        }
+        Ok(&self.peeked.as_ref().unwrap().0)
+    }
+
+    fn next(&mut self) -> Result<(Tok, usize, usize), ExprInternalError> {
+        let token = match self.peeked.take() {
+            Some(token) => token,
+            None => self.lexer.next_token()?,
+        };
+        self.last_end = token.2;
+        Ok(token)
+    }
+
+    /// start offset of the next token
+    fn start(&mut self) -> Result<usize, ExprInternalError> {
+        self.peek()?;
+        Ok(self.peeked.as_ref().unwrap().1)
+    }
+
+    fn argument(&mut self, what: &str) -> Result<String, ExprInternalError> {
+        let argument = self.lexer.argument(what)?;
+        self.last_end = self.lexer.pos();
+        Ok(argument)
+    }
+
+    /// the argument of `env` / `var`, with its shell parameter expansion if any
+    fn lookup(&mut self, lookup: fn(String) -> Expr, what: &str) -> Result<Expr, ExprInternalError> {
+        let start = self.lexer.pos();
+        let argument = self.argument(what)?;
+        match expansion::split(&argument) {
+            Ok((name, None)) => Ok(lookup(name)),
+            Ok((name, Some(expansion))) => Ok(Expr::Expand(Box::new(lookup(name)), expansion)),
+            Err(message) => Err(self.lexer.error(&message, start, self.last_end)),
+        }
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        self.lexer.span(start, self.last_end)
+    }
+
+    fn unexpected<T>(&mut self, expected: &str) -> Result<T, ExprInternalError> {
+        let (tok, start, end) = self.next()?;
+        let message = match tok {
+            Tok::End => format!("expected {}, found end of expression", expected),
+            _ => format!("expected {}", expected),
+        };
+        Err(self.lexer.error(&message, start, end))
+    }
+
+    fn expect(&mut self, op: &'static str) -> Result<(), ExprInternalError> {
+        if *self.peek()? == Tok::Op(op) {
+            self.next()?;
+            Ok(())
+        } else {
+            self.unexpected(&format!(r#""{}""#, op))
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Expr, ExprInternalError> {
+        let mut expr = self.or()?;
+        while *self.peek()? == Tok::Op("|") {
+            self.next()?;
+            let name = match self.next()? {
+                (Tok::Ident(name), _, _) => name,
+                (_, start, end) => return Err(self.lexer.error("expected filter name", start, end)),
+            };
+            let mut args = Vec::new();
+            loop {
+                let arg = match self.peek()? {
+                    Tok::Int(i) => Value::Int(*i),
+                    Tok::Float(f) => Value::Float(*f),
+                    Tok::Str(s) => Value::Str(s.clone()),
+                    _ => break,
+                };
+                self.next()?;
+                args.push(arg);
+            }
+            expr = Expr::Filter(Box::new(expr), name, args);
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        let mut expr = self.and()?;
+        while *self.peek()? == Tok::Ident("or".into()) {
+            self.next()?;
+            let right = self.and()?;
+            expr = Expr::Binary(BinOp::Or, Box::new(expr), Box::new(right), self.span_from(start));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        let mut expr = self.not()?;
+        while *self.peek()? == Tok::Ident("and".into()) {
+            self.next()?;
+            let right = self.not()?;
+            expr = Expr::Binary(BinOp::And, Box::new(expr), Box::new(right), self.span_from(start));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ExprInternalError> {
+        if *self.peek()? == Tok::Ident("not".into()) {
+            self.next()?;
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        let expr = self.sum()?;
+        let op = match self.peek()? {
+            Tok::Op("==") => BinOp::Eq,
+            Tok::Op("!=") => BinOp::Ne,
+            Tok::Op("<") => BinOp::Lt,
+            Tok::Op("<=") => BinOp::Le,
+            Tok::Op(">") => BinOp::Gt,
+            Tok::Op(">=") => BinOp::Ge,
+            _ => return Ok(expr),
+        };
+        self.next()?;
+        let right = self.sum()?;
+        Ok(Expr::Binary(op, Box::new(expr), Box::new(right), self.span_from(start)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        let mut expr = self.product()?;
+        loop {
+            let op = match self.peek()? {
+                Tok::Op("+") => BinOp::Add,
+                Tok::Op("-") => BinOp::Sub,
+                _ => return Ok(expr),
+            };
+            self.next()?;
+            let right = self.product()?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(right), self.span_from(start));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.peek()? {
+                Tok::Op("*") => BinOp::Mul,
+                Tok::Op("/") => BinOp::Div,
+                Tok::Op("%") => BinOp::Rem,
+                _ => return Ok(expr),
+            };
+            self.next()?;
+            let right = self.unary()?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(right), self.span_from(start));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprInternalError> {
+        let start = self.start()?;
+        if *self.peek()? == Tok::Op("-") {
+            self.next()?;
+            let expr = self.unary()?;
+            Ok(Expr::Neg(Box::new(expr), self.span_from(start)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprInternalError> {
+        let (tok, start, end) = match self.peek()? {
+            Tok::End => return self.unexpected("expression"),
+            _ => self.next()?,
+        };
+        match tok {
+            Tok::Int(i) => Ok(Expr::Literal(Value::Int(i))),
+            Tok::Float(f) => Ok(Expr::Literal(Value::Float(f))),
+            Tok::Str(s) => Ok(Expr::Literal(Value::Str(s))),
+            Tok::Op("(") => {
+                let expr = self.pipeline()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Tok::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                "env" => self.lookup(Expr::Env, "environment variable name"),
+                "var" => self.lookup(Expr::Var, "variable selector"),
+                "file" => Ok(Expr::File(self.argument("file path")?)),
+                "include" => Ok(Expr::Include(self.argument("template path")?)),
+                "call" => {
+                    let name = match self.next()? {
+                        (Tok::Ident(name), _, _) => name,
+                        (_, start, end) => return Err(self.lexer.error("expected macro name", start, end)),
+                    };
+                    self.expect("(")?;
+                    let mut args = Vec::new();
+                    if *self.peek()? != Tok::Op(")") {
+                        args.push(self.pipeline()?);
+                        while *self.peek()? == Tok::Op(",") {
+                            self.next()?;
+                            args.push(self.pipeline()?);
+                        }
+                    }
+                    self.expect(")")?;
+                    Ok(Expr::Call(name, args))
+                }
+                name if is_keyword(name) => Err(self.lexer.error("unexpected keyword", start, end)),
+                _ => Ok(Expr::Name(ident)),
+            },
+            _ => Err(self.lexer.error("expected expression", start, end)),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Expr, ExprInternalError> {
+    if src.trim().is_empty() {
+        return Err(ExprInternalError::UnknownExpressionType);
+    }
+    let mut parser = Parser {
+        lexer: Lexer::new(src),
+        peeked: None,
+        last_end: 0,
+    };
+    let expr = parser.pipeline()?;
+    match parser.peek()? {
+        Tok::End => Ok(expr),
+        _ => parser.unexpected("end of expression"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, BinOp, Expr, ExprInternalError, Value};
+
+    fn syntax_error(src: &str) -> (String, usize, usize) {
+        match parse(src) {
+            Err(ExprInternalError::SyntaxError(message, span)) => (message, span.start, span.end),
+            result => panic!("{:?} gave {:?}", src, result),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        match parse("1 + 2 * 3").unwrap() {
+            Expr::Binary(BinOp::Add, left, right, span) => {
+                assert!(matches!(*left, Expr::Literal(Value::Int(1))));
+                assert!(matches!(*right, Expr::Binary(BinOp::Mul, _, _, _)));
+                assert_eq!(span.text, "1 + 2 * 3");
+            }
+            expr => panic!("{:?}", expr),
+        }
+        assert!(matches!(parse("not a == b").unwrap(), Expr::Not(_)));
+        assert!(matches!(parse("a or b and c").unwrap(), Expr::Binary(BinOp::Or, _, _, _)));
+        assert!(matches!(parse("-a * b").unwrap(), Expr::Binary(BinOp::Mul, _, _, _)));
+        assert!(matches!(parse("a + b | upper").unwrap(), Expr::Filter(_, _, _)));
+    }
+
+    #[test]
+    fn primaries() {
+        assert!(matches!(parse("env HOME").unwrap(), Expr::Env(ref name) if name == "HOME"));
+        assert!(matches!(parse("var $.a.b").unwrap(), Expr::Var(ref path) if path == "$.a.b"));
+        assert!(matches!(parse("call m(1, x)").unwrap(), Expr::Call(ref name, ref args) if name == "m" && args.len() == 2));
+        assert!(matches!(parse(r#""s" | indent 2"#).unwrap(), Expr::Filter(_, ref name, ref args) if name == "indent" && args == &[Value::Int(2)]));
+        assert!(matches!(parse("null").unwrap(), Expr::Literal(Value::Null)));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(parse("  "), Err(ExprInternalError::UnknownExpressionType)));
+        assert_eq!(syntax_error("1 +"), ("expected expression, found end of expression".into(), 3, 3));
+        assert_eq!(syntax_error("(1"), (r#"expected ")", found end of expression"#.into(), 2, 2));
+        assert_eq!(syntax_error("1 2").0, "expected end of expression");
+        assert_eq!(syntax_error("1 + and").0, "unexpected keyword");
+    }
+}
@@ -0,0 +1,58 @@
+/// matches a shell pattern, `*` matches any run of characters and `?` a single character
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position after the last `*` and the text position it currently stands for
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn literal() {
+        assert!(matches("PATH", "PATH"));
+        assert!(!matches("PATH", "PATHS"));
+        assert!(!matches("PATH", "PAT"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("APP_*", "APP_PORT"));
+        assert!(matches("*PASSWORD*", "DB_PASSWORD_FILE"));
+        assert!(!matches("*PASSWORD*", "DB_PASS"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYcZ"));
+        assert!(matches("?", "é"));
+        assert!(!matches("?", ""));
+        assert!(matches("*.tar.??", "x.tar.gz"));
+        assert!(matches("**a", "bba"));
+    }
+}
@@ -0,0 +1,386 @@
+use std::io;
+
+use super::trim::trim_markers;
+use super::{ExprError, ExprInternalError, TranslateError};
+
+#[derive(Clone)]
+pub enum Token {
+    /// literal text with escapes already resolved
+    Text(String),
+    /// contents of a `${...}` tag
+    Tag(String),
+}
+
+/// character sequences that open and close a tag and the character escaping them
+#[derive(Debug, Clone)]
+pub struct Delimiters {
+    pub open: String,
+    pub close: String,
+    pub escape: char,
+    /// escape handling of earlier versions, see `--legacy-escapes`
+    pub legacy_escapes: bool,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Delimiters {
+            open: "${".into(),
+            close: "}".into(),
+            escape: '\\',
+            legacy_escapes: false,
+        }
+    }
+}
+
+const MAGIC_COMMENT: &str = "kay:delimiters";
+/// how many characters of the first line may hold the magic comment, so that long lines keep streaming
+pub const MAGIC_COMMENT_LIMIT: usize = 256;
+
+impl Delimiters {
+    pub fn new(open: &str, close: &str, escape: Option<char>) -> Result<Delimiters, String> {
+        let escape = escape.unwrap_or('\\');
+        if open.is_empty() || close.is_empty() {
+            return Err("delimiters must not be empty".into());
+        }
+        if open.contains(char::is_whitespace) || close.contains(char::is_whitespace) {
+            return Err("delimiters must not contain whitespace".into());
+        }
+        if escape.is_whitespace() || open.contains(escape) || close.contains(escape) {
+            return Err(format!(r#"escape character "{}" must not be whitespace or part of the delimiters"#, escape));
+        }
+        Ok(Delimiters {
+            open: open.into(),
+            close: close.into(),
+            escape,
+            legacy_escapes: false,
+        })
+    }
+
+    /// reads `kay:delimiters OPEN CLOSE [ESCAPE]` from the first line of a template, e.g. `# kay:delimiters [[ ]]`,
+    /// only its first `MAGIC_COMMENT_LIMIT` characters are looked at
+    pub fn from_magic_comment(template: &str) -> Option<Result<Delimiters, String>> {
+        let end = template.char_indices().nth(MAGIC_COMMENT_LIMIT).map_or(template.len(), |(i, _)| i);
+        let line = template[..end].lines().next()?;
+        let start = line.find(MAGIC_COMMENT)? + MAGIC_COMMENT.len();
+        let args = line[start..].split_whitespace().collect::<Vec<_>>();
+        Some(match args.as_slice() {
+            [open, close] => Delimiters::new(open, close, None),
+            [open, close, escape] if escape.chars().count() == 1 => Delimiters::new(open, close, escape.chars().next()),
+            _ => Err(format!("expected {} OPEN CLOSE [ESCAPE]", MAGIC_COMMENT)),
+        })
+    }
+}
+
+/// splits the input into text and tags,
+/// text is yielded line by line so that the output keeps streaming
+pub struct Scanner<'a, R> {
+    input_chars: &'a mut R,
+    delimiters: &'a Delimiters,
+    pending: Option<Token>,
+    /// inside `${raw}...${endraw}`
+    raw: bool,
+}
+
+impl<'a, R: Iterator<Item = io::Result<char>>> Scanner<'a, R> {
+    pub fn new(input_chars: &'a mut R, delimiters: &'a Delimiters) -> Self {
+        Scanner {
+            input_chars,
+            delimiters,
+            pending: None,
+            raw: false,
+        }
+    }
+
+    /// reads up to the closing delimiter, the escape character followed by the first character
+    /// of the closing delimiter writes that character, any other escape is kept for the expression
+    fn read_tag(&mut self) -> Result<Token, TranslateError> {
+        let Delimiters { close, escape, .. } = self.delimiters;
+        let mut tag = String::new();
+        let mut escaped_len = 0;
+        let mut slash = false;
+        loop {
+            let ch = match self.input_chars.next() {
+                None => return Err(TranslateError::Expr(ExprInternalError::UnexpectedEof.into())),
+                Some(Err(e)) => return Err(TranslateError::Input(e)),
+                Some(Ok(ch)) => ch,
+            };
+            if slash {
+                if !close.starts_with(ch) {
+                    tag.push(*escape);
+                }
+                tag.push(ch);
+                escaped_len = tag.len();
+                slash = false;
+            } else if ch == *escape && !self.delimiters.legacy_escapes {
+                slash = true;
+            } else {
+                tag.push(ch);
+                if tag.ends_with(close.as_str()) && tag.len() - close.len() >= escaped_len {
+                    tag.truncate(tag.len() - close.len());
+                    return Ok(Token::Tag(tag));
+                }
+            }
+        }
+    }
+
+    /// reads the text of a raw region verbatim up to `${endraw}`, line by line
+    fn read_raw(&mut self) -> Option<Result<Token, TranslateError>> {
+        let Delimiters { open, close, .. } = self.delimiters;
+        let mut text = String::new();
+        let mut tag_start = None;
+        loop {
+            let ch = match self.input_chars.next() {
+                None => return Some(Err(ExprError::from(ExprInternalError::UnclosedBlock("raw".into())).into())),
+                Some(Err(e)) => return Some(Err(TranslateError::Input(e))),
+                Some(Ok(ch)) => ch,
+            };
+            text.push(ch);
+            if ch == '\n' {
+                return Some(Ok(Token::Text(text)));
+            }
+            match tag_start {
+                Some(start) if text.ends_with(close.as_str()) && text.len() - close.len() >= start => {
+                    // tags other than the end of the region are kept verbatim
+                    let tag = &text[start..text.len() - close.len()];
+                    if trim_markers(tag).1.trim() == "endraw" {
+                        self.pending = Some(Token::Tag(tag.into()));
+                        self.raw = false;
+                        text.truncate(start - open.len());
+                        return if text.is_empty() { self.next() } else { Some(Ok(Token::Text(text))) };
+                    }
+                    tag_start = None;
+                }
+                // an earlier opening delimiter that was not closed is text
+                _ if text.ends_with(open.as_str()) => tag_start = Some(text.len()),
+                _ => {}
+            }
+        }
+    }
+
+    /// length of the longest proper prefix of the opening delimiter the text ends with
+    fn partial_open(&self, text: &str, escaped_len: usize) -> usize {
+        let open = &self.delimiters.open;
+        open.char_indices()
+            .skip(1)
+            .map(|(i, _)| i)
+            .filter(|&i| text.len() - escaped_len >= i && text.ends_with(&open[..i]))
+            .last()
+            .unwrap_or(0)
+    }
+}
+
+impl<'a, R: Iterator<Item = io::Result<char>>> Iterator for Scanner<'a, R> {
+    type Item = Result<Token, TranslateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+        if self.raw {
+            return self.read_raw();
+        }
+        let Delimiters { open, escape, .. } = self.delimiters;
+        let mut text = String::new();
+        // escaped characters before this offset cannot be part of an opening delimiter
+        let mut escaped_len = 0;
+        let mut slash = false;
+
+        while let Some(rch) = self.input_chars.next() {
+            let ch = match rch {
+                Ok(ch) => ch,
+                Err(e) => return Some(Err(TranslateError::Input(e))),
+            };
+            if slash {
+                if open.starts_with(ch) {
+                    text.push(ch);
+                } else if ch == *escape {
+                    text.push(ch);
+                    if self.delimiters.legacy_escapes {
+                        text.push(ch);
+                    }
+                } else if !self.delimiters.legacy_escapes {
+                    text.push(*escape);
+                    text.push(ch);
+                }
+                escaped_len = text.len();
+                slash = false;
+                if ch == '\n' && !self.delimiters.legacy_escapes {
+                    return Some(Ok(Token::Text(text)));
+                }
+                continue;
+            }
+            let partial = if self.delimiters.legacy_escapes {
+                self.partial_open(&text, escaped_len)
+            } else {
+                0
+            };
+            if partial > 0 && !open[partial..].starts_with(ch) {
+                // earlier versions dropped a partial opening delimiter together with the next character
+                text.truncate(text.len() - partial);
+            } else if ch == *escape {
+                slash = true;
+            } else {
+                text.push(ch);
+                if text.ends_with(open.as_str()) && text.len() - open.len() >= escaped_len {
+                    text.truncate(text.len() - open.len());
+                    let tag = self.read_tag();
+                    if let Ok(Token::Tag(tag)) = &tag {
+                        match trim_markers(tag).1.trim() {
+                            "raw" => self.raw = true,
+                            "endraw" => {
+                                let error = ExprInternalError::UnexpectedEnd("endraw".into());
+                                return Some(Err(ExprError::from(error).into()));
+                            }
+                            _ => {}
+                        }
+                    }
+                    if text.is_empty() {
+                        return Some(tag);
+                    }
+                    match tag {
+                        Ok(tag) => self.pending = Some(tag),
+                        Err(e) => return Some(Err(e)),
+                    }
+                    return Some(Ok(Token::Text(text)));
+                }
+                if ch == '\n' {
+                    return Some(Ok(Token::Text(text)));
+                }
+            }
+        }
+        if slash {
+            text.push(*escape);
+        }
+        if text.is_empty() {
+            None
+        } else {
+            Some(Ok(Token::Text(text)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delimiters, Scanner, Token, TranslateError};
+
+    /// the tokens of `input`, tags written as `[tag]`, text as it is
+    fn scan_with(input: &str, delimiters: &Delimiters) -> Result<String, TranslateError> {
+        let mut chars = input.chars().map(Ok);
+        Scanner::new(&mut chars, delimiters)
+            .map(|token| match token? {
+                Token::Text(text) => Ok(text),
+                Token::Tag(tag) => Ok(format!("[{}]", tag)),
+            })
+            .collect()
+    }
+
+    fn scan(input: &str) -> String {
+        scan_with(input, &Delimiters::default()).unwrap_or_else(|_| panic!("scanning {:?} failed", input))
+    }
+
+    fn scan_legacy(input: &str) -> String {
+        let delimiters = Delimiters {
+            legacy_escapes: true,
+            ..Delimiters::default()
+        };
+        scan_with(input, &delimiters).unwrap_or_else(|_| panic!("scanning {:?} failed", input))
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(scan("a ${x} b ${ y }"), "a [x] b [ y ]");
+        assert_eq!(scan("${x}${y}"), "[x][y]");
+        assert!(scan_with("a ${x", &Delimiters::default()).is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(scan(r"\${x}"), "${x}");
+        assert_eq!(scan(r"\$x"), "$x");
+        assert_eq!(scan(r"a\\b"), r"a\b");
+        assert_eq!(scan(r"\\${x}"), r"\[x]");
+        assert_eq!(scan(r"\x \n"), r"\x \n");
+        assert_eq!(scan("a\\"), "a\\");
+    }
+
+    #[test]
+    fn lone_dollar() {
+        assert_eq!(scan("$"), "$");
+        assert_eq!(scan("cost $5 $x ${x}"), "cost $5 $x [x]");
+        assert_eq!(scan("$$${x}"), "$$[x]");
+    }
+
+    #[test]
+    fn escaped_close_inside_a_tag() {
+        assert_eq!(scan(r#"${"{\}" | raw}"#), r#"["{}" | raw]"#);
+        // other escapes are kept for the expression
+        assert_eq!(scan(r#"${"a\"b"}"#), r#"["a\"b"]"#);
+        assert_eq!(scan(r"${x\\}"), r"[x\\]");
+    }
+
+    #[test]
+    fn legacy_escapes() {
+        assert_eq!(scan_legacy(r"\${x}"), "${x}");
+        assert_eq!(scan_legacy(r"\$x"), "$x");
+        assert_eq!(scan_legacy(r"a\\b"), r"a\\b");
+        assert_eq!(scan_legacy(r"a\xb"), "ab");
+        assert_eq!(scan_legacy("a$xb ${x}"), "ab [x]");
+        assert_eq!(scan_legacy("a$"), "a$");
+        assert_eq!(scan_legacy("a\\"), "a\\");
+        // the escape character has no meaning inside a tag
+        assert_eq!(scan_legacy(r"${a\}b"), r"[a\]b");
+    }
+
+    #[test]
+    fn multi_character_delimiters() {
+        let delimiters = Delimiters::new("[[", "]]", None).unwrap();
+        let scan = |input: &str| scan_with(input, &delimiters).unwrap_or_else(|_| panic!("scanning {:?} failed", input));
+        assert_eq!(scan("a [[x]] ${y} [b] [[ [z] ]]"), "a [x] ${y} [b] [ [z] ]");
+        assert_eq!(scan("[[[x]]"), "[[x]");
+        assert_eq!(scan(r"\[[x]]"), "[[x]]");
+        assert_eq!(scan(r"[[a\]]]"), "[a]]");
+        assert_eq!(scan("a [ b ["), "a [ b [");
+        let legacy = Delimiters {
+            legacy_escapes: true,
+            ..delimiters.clone()
+        };
+        assert_eq!(scan_with("a[b [[x]]", &legacy).ok().as_deref(), Some("a [x]"));
+        let custom_escape = Delimiters::new("<%", "%>", Some('@')).unwrap();
+        assert_eq!(scan_with(r"@<% \ <%x%>", &custom_escape).ok().as_deref(), Some(r"<% \ [x]"));
+    }
+
+    #[test]
+    fn text_is_split_into_lines() {
+        let mut chars = "a\nb ${x}\nc".chars().map(Ok);
+        let delimiters = Delimiters::default();
+        let texts = Scanner::new(&mut chars, &delimiters)
+            .map(|token| match token {
+                Ok(Token::Text(text)) => text,
+                Ok(Token::Tag(tag)) => format!("[{}]", tag),
+                Err(_) => panic!("scanning failed"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["a\n", "b ", "[x]", "\n", "c"]);
+    }
+
+    #[test]
+    fn raw_regions() {
+        assert_eq!(scan("${raw}a ${x} \\${y}\n${endraw} ${z}"), "[raw]a ${x} \\${y}\n[endraw] [z]");
+        assert_eq!(scan("${raw}${endraw}"), "[raw][endraw]");
+        assert_eq!(scan("${raw}${- endraw -}"), "[raw][- endraw -]");
+        // an opening delimiter that is never closed does not hide the end of the region
+        assert_eq!(scan("${raw}${ ${endraw}${x}"), "[raw]${ [endraw][x]");
+        assert_eq!(scan("${raw}${a ${endraw} b}\n"), "[raw]${a [endraw] b}\n");
+        assert!(scan_with("${raw}${endraw}${endraw}", &Delimiters::default()).is_err());
+        // comments and other tags are kept as they are, including delimiters inside them
+        assert_eq!(scan("${raw}${# ${x} }${endraw}"), "[raw]${# ${x} }[endraw]");
+        assert!(scan_with("${raw}a\nb", &Delimiters::default()).is_err());
+    }
+
+    #[test]
+    fn comments_holding_delimiters() {
+        assert_eq!(scan("${# use ${ to open a tag }a"), "[# use ${ to open a tag ]a");
+        assert_eq!(scan(r"${# close with \} }a"), "[# close with } ]a");
+        assert_eq!(scan("${# spans\nlines }a"), "[# spans\nlines ]a");
+    }
+}
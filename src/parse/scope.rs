@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::macros::Macro;
+use super::Value;
+
+enum Opener {
+    Template,
+    /// a macro call
+    Call(String),
+    /// an `${if ...}` or `${for ...}` block
+    Block,
+}
+
+struct Frame {
+    opener: Opener,
+    locals: HashMap<String, Value>,
+}
+
+/// mutable state of a translation: macro definitions and local variables
+///
+/// the template's own `${let ...}` bindings are visible everywhere after they are made,
+/// a macro call additionally sees its parameters and its own bindings, but not those of its caller,
+/// bindings made in an `${if ...}` or `${for ...}` block end with it and may shadow outer ones
+pub struct Scope {
+    frames: Vec<Frame>,
+    macros: HashMap<String, Rc<Macro>>,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope {
+            frames: vec![Frame {
+                opener: Opener::Template,
+                locals: HashMap::new(),
+            }],
+            macros: HashMap::new(),
+        }
+    }
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// looks `name` up in the blocks of the current call (or of the template) from the innermost, then in the template
+    pub fn local(&self, name: &str) -> Option<&Value> {
+        let call = self
+            .frames
+            .iter()
+            .rposition(|frame| !matches!(frame.opener, Opener::Block))
+            .unwrap_or(0);
+        let template = self.frames.first().filter(|_| call > 0);
+        self.frames[call..]
+            .iter()
+            .rev()
+            .chain(template)
+            .find_map(|frame| frame.locals.get(name))
+    }
+
+    /// binds `name` in the innermost frame, replacing any previous binding there
+    pub fn bind(&mut self, name: String, value: Value) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.locals.insert(name, value);
+        }
+    }
+
+    pub fn is_calling(&self, name: &str) -> bool {
+        self.frames
+            .iter()
+            .any(|frame| matches!(&frame.opener, Opener::Call(call) if call == name))
+    }
+
+    pub fn push_call(&mut self, call: String, locals: HashMap<String, Value>) {
+        self.frames.push(Frame {
+            opener: Opener::Call(call),
+            locals,
+        });
+    }
+
+    pub fn pop_call(&mut self) {
+        self.frames.pop();
+    }
+
+    pub fn push_block(&mut self) {
+        self.frames.push(Frame {
+            opener: Opener::Block,
+            locals: HashMap::new(),
+        });
+    }
+
+    pub fn pop_block(&mut self) {
+        self.frames.pop();
+    }
+
+    pub fn define(&mut self, definition: Macro) {
+        self.macros.insert(definition.name.clone(), Rc::new(definition));
+    }
+
+    pub fn get_macro(&self, name: &str) -> Option<Rc<Macro>> {
+        self.macros.get(name).cloned()
+    }
+
+    /// makes the macros defined by a library available in this scope
+    pub fn import(&mut self, library: Scope) {
+        self.macros.extend(library.macros);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Scope, Value};
+
+    fn local(scope: &Scope, name: &str) -> Option<i64> {
+        match scope.local(name) {
+            Some(Value::Int(i)) => Some(*i),
+            Some(value) => panic!("{:?}", value),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut scope = Scope::new();
+        scope.bind("t".into(), Value::Int(1));
+        scope.push_block();
+        scope.bind("t".into(), Value::Int(2));
+        scope.bind("b".into(), Value::Int(2));
+        assert_eq!((local(&scope, "t"), local(&scope, "b")), (Some(2), Some(2)));
+
+        // a call sees its parameters and the template frame, not the blocks it was called from
+        let params = vec![(String::from("p"), Value::Int(3))].into_iter().collect::<HashMap<_, _>>();
+        scope.push_call("m".into(), params);
+        assert!(scope.is_calling("m") && !scope.is_calling("n"));
+        assert_eq!((local(&scope, "t"), local(&scope, "b"), local(&scope, "p")), (Some(1), None, Some(3)));
+        scope.push_block();
+        scope.bind("p".into(), Value::Int(4));
+        assert_eq!(local(&scope, "p"), Some(4));
+        scope.pop_block();
+        assert_eq!(local(&scope, "p"), Some(3));
+        scope.bind("c".into(), Value::Int(5));
+        scope.pop_call();
+
+        assert!(!scope.is_calling("m"));
+        assert_eq!((local(&scope, "c"), local(&scope, "p"), local(&scope, "b")), (None, None, Some(2)));
+        scope.pop_block();
+        assert_eq!((local(&scope, "t"), local(&scope, "b")), (Some(1), None));
+    }
+}
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+
+use super::pattern::matches;
+use super::Value;
+
+/// what secret values are replaced with
+pub const MASK: &[u8] = b"****";
+
+/// values masked in diagnostics and diffs, the rendered output keeps them
+pub struct Secrets {
+    /// patterns of the env names and vars selectors holding secrets, in lowercase
+    patterns: Vec<String>,
+    /// every secret value read while translating
+    values: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Secrets {
+    /// `patterns` match env names and vars selectors such as `*PASSWORD*` regardless of case
+    pub fn new(patterns: &[String]) -> Self {
+        Secrets {
+            patterns: patterns.iter().map(|pattern| pattern.to_lowercase()).collect(),
+            values: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// secrets of `patterns` that already hold the values of the matching env variables,
+    /// for the errors that happen before any template is translated
+    pub fn from_env(patterns: &[String]) -> Self {
+        let secrets = Secrets::new(patterns);
+        for (name, value) in std::env::vars_os() {
+            let value = Value::from_bytes(value.into_encoded_bytes());
+            secrets.lookup(&name.to_string_lossy(), &value);
+        }
+        secrets
+    }
+
+    /// remembers the value of the env variable or vars selector `name` if it holds a secret
+    pub fn lookup(&self, name: &str, value: &Value) {
+        let name = name.to_lowercase();
+        if self.patterns.iter().any(|pattern| matches(pattern, &name)) {
+            self.add(value);
+        }
+    }
+
+    /// remembers a secret value, the items of lists and maps are masked one by one
+    pub fn add(&self, value: &Value) {
+        match value {
+            Value::Null => {}
+            Value::List(items) => items.iter().for_each(|item| self.add(item)),
+            Value::Map(entries) => entries.iter().for_each(|(_, item)| self.add(item)),
+            value => {
+                let bytes = value.clone().into_bytes();
+                let mut values = self.values.borrow_mut();
+                if !bytes.is_empty() && !values.contains(&bytes) {
+                    values.push(bytes);
+                }
+            }
+        }
+    }
+
+    /// replaces every secret in `text`
+    pub fn mask(&self, text: &[u8]) -> Vec<u8> {
+        let mut values = self.values.borrow().clone();
+        if values.is_empty() {
+            return text.to_vec();
+        }
+        // a secret containing another one is masked as a whole
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        let mut masked = Vec::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            match values.iter().find(|value| rest.starts_with(value)) {
+                Some(value) => {
+                    masked.extend_from_slice(MASK);
+                    rest = &rest[value.len()..];
+                }
+                None => {
+                    masked.push(rest[0]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        masked
+    }
+
+    /// byte ranges of every secret in `text`
+    pub fn ranges(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        for value in self.values.borrow().iter() {
+            let mut from = 0;
+            while let Some(i) = text[from..].windows(value.len()).position(|window| window == value.as_slice()) {
+                ranges.push((from + i, from + i + value.len()));
+                from += i + 1;
+            }
+        }
+        ranges
+    }
+
+    pub fn mask_str(&self, text: &str) -> String {
+        String::from_utf8_lossy(&self.mask(text.as_bytes())).into_owned()
+    }
+}
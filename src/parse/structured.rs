@@ -0,0 +1,146 @@
+use std::io::{self, Write};
+
+use serde_yaml::{Mapping, Value as YamlValue};
+
+use super::expr::{evaluate, is_comment, keyword};
+use super::scanner::{Scanner, Token};
+use super::trim::Trim;
+use super::{translate_tokens, Context, Scope, TranslateError, VarsFormat};
+
+/// translates a single string scalar, a scalar that is nothing but one expression
+/// is replaced by the typed value of that expression
+fn translate_scalar(scalar: &str, ctx: &Context, scope: &mut Scope) -> Result<YamlValue, TranslateError> {
+    let mut chars = scalar.chars().map(Ok);
+    let tokens = Trim::new(Scanner::new(&mut chars, &ctx.delimiters), ctx.trim_blocks).collect::<Result<Vec<_>, _>>()?;
+    if let [Token::Tag(tag)] = tokens.as_slice() {
+        match keyword(tag).0 {
+            _ if is_comment(tag) => {}
+            "define" | "if" | "else" | "for" | "end" | "raw" | "endraw" | "import" | "let" => {}
+            _ => return Ok(evaluate(tag, ctx, scope)?.to_yaml()),
+        }
+    }
+    let mut output = Vec::new();
+    translate_tokens(&mut tokens.into_iter().map(Ok), &mut output, ctx, scope)?;
+    Ok(YamlValue::String(String::from_utf8_lossy(&output).into_owned()))
+}
+
+/// translates every string scalar (and string key) of a parsed template,
+/// errors carry the jsonpath of the offending scalar
+pub fn translate_document(
+    document: YamlValue,
+    path: &str,
+    ctx: &Context,
+    scope: &mut Scope,
+) -> Result<YamlValue, (String, TranslateError)> {
+    match document {
+        YamlValue::String(scalar) => translate_scalar(&scalar, ctx, scope).map_err(|e| (path.into(), e)),
+        YamlValue::Sequence(items) => items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| translate_document(item, &format!("{}[{}]", path, i), ctx, scope))
+            .collect::<Result<_, _>>()
+            .map(YamlValue::Sequence),
+        YamlValue::Mapping(mapping) => {
+            let mut translated = Mapping::new();
+            for (key, value) in mapping {
+                let key_path = match &key {
+                    YamlValue::String(key) => format!("{}.{}", path, key),
+                    key => format!("{}[{:?}]", path, key),
+                };
+                let key = match key {
+                    YamlValue::String(key) => {
+                        let mut output = Vec::new();
+                        let mut chars = key.chars().map(Ok);
+                        let mut tokens = Trim::new(Scanner::new(&mut chars, &ctx.delimiters), ctx.trim_blocks);
+                        translate_tokens(&mut tokens, &mut output, ctx, scope).map_err(|e| (key_path.clone(), e))?;
+                        YamlValue::String(String::from_utf8_lossy(&output).into_owned())
+                    }
+                    key => key,
+                };
+                let value = translate_document(value, &key_path, ctx, scope)?;
+                translated.insert(key, value);
+            }
+            Ok(YamlValue::Mapping(translated))
+        }
+        scalar => Ok(scalar),
+    }
+}
+
+pub fn write_document<W: Write>(document: &YamlValue, format: &VarsFormat, output: &mut W) -> io::Result<()> {
+    let to_io = |e: Box<dyn std::error::Error + Send + Sync>| io::Error::new(io::ErrorKind::InvalidData, e);
+    match format {
+        VarsFormat::Json => serde_json::to_writer_pretty(&mut *output, document).map_err(|e| to_io(e.into()))?,
+        VarsFormat::Yaml => serde_yaml::to_writer(&mut *output, document).map_err(|e| to_io(e.into()))?,
+        VarsFormat::Toml => unreachable!("rejected by cli_args"),
+    }
+    output.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serde_yaml::Value as YamlValue;
+
+    use super::super::testing::Setup;
+    use super::super::{ExprError, ExprInternalError};
+    use super::{translate_document, write_document, TranslateError, VarsFormat};
+
+    const NASTY: &str = "a: \"b\" #c\n- {d: [e]}\n\n  'f'";
+
+    fn translate(document: YamlValue) -> Result<YamlValue, (String, TranslateError)> {
+        let setup = Setup {
+            vars: Some(json!({"nasty": NASTY, "port": 8080, "ratio": 0.5, "on": true, "none": null, "list": [1, "two"], "map": {"k": "v"}})),
+            ..Setup::default()
+        };
+        setup.with_context(|ctx, scope| translate_document(document, "$", ctx, scope))
+    }
+
+    fn render(document: YamlValue, format: VarsFormat) -> String {
+        let mut output = Vec::new();
+        write_document(&translate(document).ok().unwrap(), &format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn values_stay_single_scalars() {
+        let template = serde_yaml::from_str("pw: ${var $.nasty}\nlabel: x ${var $.nasty} y\nnext: 1\n").unwrap();
+        let rendered: YamlValue = serde_yaml::from_str(&render(template, VarsFormat::Yaml)).unwrap();
+        assert_eq!(rendered["pw"].as_str(), Some(NASTY));
+        assert_eq!(rendered["label"].as_str(), Some(format!("x {} y", NASTY).as_str()));
+        assert_eq!(rendered.as_mapping().unwrap().len(), 3);
+
+        let template = serde_json::from_str(r#"{"pw": "${var $.nasty}", "next": 1}"#).unwrap();
+        let rendered: serde_json::Value = serde_json::from_str(&render(template, VarsFormat::Json)).unwrap();
+        assert_eq!(rendered, json!({"pw": NASTY, "next": 1}));
+    }
+
+    #[test]
+    fn typed_values() {
+        let template = "port: ${var $.port}\nratio: ${var $.ratio}\non: ${var $.on}\nnone: ${var $.none}\nlist: ${var $.list}\nmap: ${var $.map}\n";
+        let rendered = render(serde_yaml::from_str(template).unwrap(), VarsFormat::Yaml);
+        assert_eq!(
+            serde_yaml::from_str::<YamlValue>(&rendered).unwrap(),
+            serde_yaml::from_str::<YamlValue>("port: 8080\nratio: 0.5\non: true\nnone: null\nlist: [1, two]\nmap: {k: v}").unwrap()
+        );
+        assert!(rendered.contains("port: 8080\n"), "{}", rendered);
+
+        // scalars that are not strings are kept, text around an expression makes a string
+        let template = "count: 3\nquoted: \"3\"\nflag: false\nport: ${var $.port}x\n";
+        let rendered = render(serde_yaml::from_str(template).unwrap(), VarsFormat::Yaml);
+        assert_eq!(rendered, "---\ncount: 3\nquoted: \"3\"\nflag: false\nport: 8080x\n");
+        let template = serde_json::from_str(r#"{"count": 3, "quoted": "3", "port": "${var $.port}"}"#).unwrap();
+        assert_eq!(render(template, VarsFormat::Json), "{\n  \"count\": 3,\n  \"quoted\": \"3\",\n  \"port\": 8080\n}\n");
+    }
+
+    #[test]
+    fn keys_and_errors() {
+        let rendered = render(serde_yaml::from_str("${var $.map.k}_key: 1").unwrap(), VarsFormat::Json);
+        assert_eq!(rendered, "{\n  \"v_key\": 1\n}\n");
+        match translate(serde_yaml::from_str("a: [1, \"${nope}\"]").unwrap()) {
+            Err((path, TranslateError::Expr(ExprError::Internal(ExprInternalError::UnknownName(name))))) => {
+                assert_eq!((path.as_str(), name.as_str()), ("$.a[1]", "nope"));
+            }
+            _ => panic!("translating an unknown name succeeded"),
+        }
+    }
+}
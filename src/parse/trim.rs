@@ -0,0 +1,167 @@
+use std::mem;
+
+use super::expr::{is_comment, keyword};
+use super::scanner::Token;
+use super::TranslateError;
+
+/// splits the markers off `${- tag -}`, a `-` next to the delimiter followed by whitespace
+/// trims the whitespace before / after the tag
+pub fn trim_markers(tag: &str) -> (bool, &str, bool) {
+    let before = tag.starts_with('-') && tag[1..].starts_with(char::is_whitespace);
+    let tag = if before { &tag[1..] } else { tag };
+    let after = tag.ends_with('-') && tag[..tag.len() - 1].ends_with(char::is_whitespace);
+    let tag = if after { &tag[..tag.len() - 1] } else { tag };
+    (before, tag, after)
+}
+
+/// tags that do not write anything themselves
+fn is_block(tag: &str) -> bool {
+    match keyword(tag).0 {
+        "define" | "if" | "else" | "for" | "end" | "import" | "let" | "raw" | "endraw" => true,
+        _ => is_comment(tag),
+    }
+}
+
+/// applies the trim markers and, with `trim_blocks`, removes the indentation before
+/// and the newline after a block tag so that a line holding only a block tag disappears
+pub struct Trim<T> {
+    tokens: T,
+    trim_blocks: bool,
+    /// trailing whitespace of the text so far, held back until the next tag shows whether it is trimmed
+    held: String,
+    /// nothing but whitespace and block tags since the last newline
+    line_start: bool,
+    /// the last tag ended with `-}`
+    trim_next: bool,
+    /// the last tag was a block tag
+    trim_line: bool,
+    pending: Option<Token>,
+}
+
+impl<T> Trim<T> {
+    pub fn new(tokens: T, trim_blocks: bool) -> Self {
+        Trim {
+            tokens,
+            trim_blocks,
+            held: String::new(),
+            line_start: true,
+            trim_next: false,
+            trim_line: false,
+            pending: None,
+        }
+    }
+}
+
+impl<T: Iterator<Item = Result<Token, TranslateError>>> Iterator for Trim<T> {
+    type Item = Result<Token, TranslateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+        loop {
+            match self.tokens.next() {
+                None if self.held.is_empty() => return None,
+                None => return Some(Ok(Token::Text(mem::take(&mut self.held)))),
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(Token::Text(text))) => {
+                    let mut text = text.as_str();
+                    if self.trim_next {
+                        text = text.trim_start();
+                        if text.is_empty() {
+                            continue;
+                        }
+                        self.trim_next = false;
+                    }
+                    if self.trim_line {
+                        self.trim_line = false;
+                        let rest = text.trim_start_matches([' ', '\t']);
+                        if let Some(rest) = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n')) {
+                            text = rest;
+                        }
+                    }
+                    let body_len = text.trim_end().len();
+                    if body_len == 0 {
+                        self.held.push_str(text);
+                        continue;
+                    }
+                    let mut output = mem::take(&mut self.held);
+                    output.push_str(&text[..body_len]);
+                    self.held.push_str(&text[body_len..]);
+                    self.line_start = false;
+                    return Some(Ok(Token::Text(output)));
+                }
+                Some(Ok(Token::Tag(tag))) => {
+                    let (before, inner, after) = trim_markers(&tag);
+                    let block = self.trim_blocks && is_block(inner);
+                    if before {
+                        self.held.clear();
+                    } else if block && (self.line_start || self.held.contains('\n')) {
+                        let indentation = self.held.rfind('\n').map_or(0, |i| i + 1);
+                        self.held.truncate(indentation);
+                    }
+                    self.trim_next = after;
+                    self.trim_line = block && !after;
+                    self.line_start = (self.line_start || self.held.contains('\n')) && is_block(inner);
+                    let tag = Token::Tag(inner.into());
+                    if self.held.is_empty() {
+                        return Some(Ok(tag));
+                    }
+                    self.pending = Some(tag);
+                    return Some(Ok(Token::Text(mem::take(&mut self.held))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::Setup;
+
+    fn render(template: &str, trim_blocks: bool) -> String {
+        let setup = Setup { trim_blocks, ..Setup::default() };
+        setup.translate_str(template).ok().unwrap()
+    }
+
+    #[test]
+    fn markers_across_lines() {
+        assert_eq!(render("a\n  ${- 1}", false), "a1");
+        assert_eq!(render("${1 -}\n  b", false), "1b");
+        assert_eq!(render("a \n\n${- 1 -} \n b", false), "a1b");
+        // a `-` not followed by whitespace is a negative number
+        assert_eq!(render("a ${-1}", false), "a -1");
+    }
+
+    #[test]
+    fn markers_next_to_tags() {
+        assert_eq!(render("${1 -}  ${2}", false), "12");
+        assert_eq!(render("${1}  ${- 2}", false), "12");
+        assert_eq!(render("${1 -}\n${- 2}", false), "12");
+        assert_eq!(render("${1 -}  ${# c }  ${2}", false), "1  2");
+    }
+
+    #[test]
+    fn block_lines() {
+        assert_eq!(render("a\n  ${let x = 1}\nb ${x}\n", false), "a\n  \nb 1\n");
+        assert_eq!(render("a\n  ${let x = 1}\nb ${x}\n", true), "a\nb 1\n");
+        assert_eq!(render("${let x = 1}\nb", true), "b");
+        assert_eq!(render("a\n  ${# c }  \n\tb", true), "a\n\tb");
+        assert_eq!(render("${define m()}\n  x\n${end}\n${call m()}\n", true), "  x\n\n");
+        assert_eq!(render("${if true}\n  a\n${else}\n  b\n${end}\n", true), "  a\n");
+        // the newline after a block tag is removed even when text precedes the tag
+        assert_eq!(render("a ${let x = 1}\nb", true), "a b");
+        // tags that write something are kept on their line
+        assert_eq!(render("  ${1}\n", true), "  1\n");
+    }
+
+    #[test]
+    fn raw_regions() {
+        // markers inside a raw region are text
+        assert_eq!(render("${raw}a  ${- x}\n${endraw}", true), "a  ${- x}\n");
+        assert_eq!(render("a  ${- raw}b${endraw}", false), "ab");
+        assert_eq!(render("${raw -}\n  x${endraw}", false), "x");
+        assert_eq!(render("  ${raw}\n${x}\n  ${endraw}\nb", false), "  \n${x}\n  \nb");
+        assert_eq!(render("  ${raw}\n${x}\n  ${endraw}\nb", true), "${x}\nb");
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::io::Result;
 
 pub struct PositionIterator<T>
@@ -8,6 +9,8 @@ where
     index: usize,
     line: usize,
     col: usize,
+    /// read ahead by `peek_line`, not yielded yet
+    peeked: VecDeque<Result<char>>,
 }
 
 impl<T> Iterator for PositionIterator<T>
@@ -16,15 +19,15 @@ where
 {
     type Item = Result<char>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.it.next().map(|ch| {
-            ch.map(|ch| {
+        let next = self.peeked.pop_front().or_else(|| self.it.next());
+        next.map(|ch| {
+            ch.inspect(|&ch| {
                 if ch == '\n' {
                     self.col = 0;
                     self.line += 1;
                 }
                 self.col += 1;
                 self.index += 1;
-                ch
             })
         })
     }
@@ -40,6 +43,17 @@ where
     pub fn col(&self) -> usize {
         self.col
     }
+    /// the rest of the current line, at most `limit` characters, without consuming it
+    pub fn peek_line(&mut self, limit: usize) -> String {
+        let ends_line = |ch: &Result<char>| !matches!(ch, Ok(ch) if *ch != '\n');
+        while self.peeked.len() < limit && !self.peeked.back().is_some_and(ends_line) {
+            match self.it.next() {
+                Some(ch) => self.peeked.push_back(ch),
+                None => break,
+            }
+        }
+        self.peeked.iter().map_while(|ch| ch.as_ref().ok()).collect()
+    }
     // pub fn index(&self) -> usize {
     //     self.index
     // }
@@ -55,6 +69,7 @@ where
             index: 0,
             line: 1,
             col: 1,
+            peeked: VecDeque::new(),
         }
     }
 }
@@ -1,6 +1,8 @@
-use std::path::PathBuf;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, process};
 
 use super::Error;
 
@@ -29,19 +31,177 @@ impl<'a> Read for InputStream<'a> {
     }
 }
 
+/// output kept in memory up to this size before it moves to a temporary file
+const MEMORY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// how an output file is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteMode {
+    /// while translating
+    Stream,
+    /// in place once translating succeeded
+    Buffered,
+    /// by renaming a temporary file over it once translating succeeded
+    Atomic,
+}
+
+/// the file `target` ends up at when it is a symlink, possibly a dangling one
+fn resolve_links(target: &Path) -> PathBuf {
+    let mut path = target.to_path_buf();
+    // the limit of the kernel, against link loops
+    for _ in 0..40 {
+        match fs::read_link(&path) {
+            Ok(link) => path = path.parent().map_or_else(|| link.clone(), |dir| dir.join(&link)),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// a file next to the target that replaces it on commit and is removed otherwise
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    /// the target with its symlinks resolved, the links themselves stay in place
+    target: PathBuf,
+    file: BufWriter<File>,
+    committed: bool,
+}
+
+impl TempFile {
+    fn create(target: &Path) -> io::Result<TempFile> {
+        let target = resolve_links(target);
+        let name = target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let path = target.with_file_name(format!(".{}.kay-{}", name, process::id()));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        // keep the permissions of the file being replaced
+        if let Ok(metadata) = fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        Ok(TempFile {
+            path,
+            target,
+            file: BufWriter::new(file),
+            committed: false,
+        })
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, &self.target)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// tells apart the temporary files of the outputs of one run
+static SPILLED: AtomicUsize = AtomicUsize::new(0);
+
+/// output held in memory, or in an already unlinked temporary file once it grows large
+#[derive(Debug, Default)]
+pub struct SpillBuffer {
+    memory: Vec<u8>,
+    spilled: Option<File>,
+}
+
+impl SpillBuffer {
+    fn spill(&mut self) -> io::Result<()> {
+        let count = SPILLED.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("kay-{}-{}", process::id(), count));
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        fs::remove_file(&path)?;
+        file.write_all(&self.memory)?;
+        self.memory = Vec::new();
+        self.spilled = Some(file);
+        Ok(())
+    }
+
+    fn copy_to<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        match &mut self.spilled {
+            Some(file) => {
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(file, output)?;
+            }
+            None => output.write_all(&self.memory)?,
+        }
+        output.flush()
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.spilled.is_none() && self.memory.len() + buf.len() > MEMORY_LIMIT {
+            self.spill()?;
+        }
+        match &mut self.spilled {
+            Some(file) => file.write(buf),
+            None => self.memory.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum OutputStream<'a> {
     File(PathBuf, BufWriter<File>),
     Stdout(io::StdoutLock<'a>),
+    /// held back until the output is complete, then written over the target in place
+    BufferedFile(PathBuf, SpillBuffer),
+    /// written to a temporary file that replaces the target once the output is complete
+    AtomicFile(PathBuf, TempFile),
+    /// held back until the output is complete
+    BufferedStdout(io::StdoutLock<'a>, SpillBuffer),
+    /// kept in memory to be compared with the target, which is never written
+    Memory(PathBuf, Vec<u8>),
 }
 impl<'a> OutputStream<'a> {
-    pub fn from_file(path: PathBuf) -> Result<OutputStream<'a>, Error<'a>> {
-        let file = File::open(&path)
-            .map_err(|e| Error::FileWrite(path.clone(), e))?;
-        Ok(OutputStream::File(path, BufWriter::new(file)))
+    /// unless `mode` is `Stream` nothing is written before `commit`
+    pub fn from_file(path: PathBuf, mode: WriteMode) -> Result<OutputStream<'a>, Error<'a>> {
+        match mode {
+            WriteMode::Stream => {
+                let file = File::create(&path)
+                    .map_err(|e| Error::FileWrite(path.clone(), e))?;
+                Ok(OutputStream::File(path, BufWriter::new(file)))
+            }
+            WriteMode::Buffered => Ok(OutputStream::BufferedFile(path, SpillBuffer::default())),
+            WriteMode::Atomic => {
+                let file = TempFile::create(&path)
+                    .map_err(|e| Error::FileWrite(path.clone(), e))?;
+                Ok(OutputStream::AtomicFile(path, file))
+            }
+        }
+    }
+    pub fn in_memory(path: PathBuf) -> OutputStream<'a> {
+        OutputStream::Memory(path, Vec::new())
+    }
+    pub fn from_stdin(stdout: &io::Stdout, stream: bool) -> OutputStream<'_> {
+        if stream {
+            OutputStream::Stdout(stdout.lock())
+        } else {
+            OutputStream::BufferedStdout(stdout.lock(), SpillBuffer::default())
+        }
     }
-    pub fn from_stdin(stdout: &io::Stdout) -> OutputStream {
-        OutputStream::Stdout(stdout.lock())
+    /// writes the held back output to its target, to be called once translating succeeded
+    pub fn commit(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(_, file) => file.flush(),
+            Self::Stdout(stdout) => stdout.flush(),
+            // truncating and writing in place follows symlinks and works for bind mounted files
+            Self::BufferedFile(path, buffer) => buffer.copy_to(&mut BufWriter::new(File::create(path)?)),
+            Self::AtomicFile(_, file) => file.commit(),
+            Self::BufferedStdout(stdout, buffer) => buffer.copy_to(stdout),
+            Self::Memory(..) => Ok(()),
+        }
     }
 }
 impl<'a> Write for OutputStream<'a> {
@@ -49,12 +209,76 @@ impl<'a> Write for OutputStream<'a> {
         match self {
             Self::File(_, file) => file.write(buf),
             Self::Stdout(stdin) => stdin.write(buf),
+            Self::BufferedFile(_, buffer) => buffer.write(buf),
+            Self::AtomicFile(_, file) => file.file.write(buf),
+            Self::BufferedStdout(_, buffer) => buffer.write(buf),
+            Self::Memory(_, buffer) => buffer.write(buf),
         }
     }
     fn flush(&mut self) -> io::Result<()> {
         match self {
             Self::File(_, file) => file.flush(),
             Self::Stdout(stdin) => stdin.flush(),
+            Self::BufferedFile(_, buffer) => buffer.flush(),
+            Self::AtomicFile(_, file) => file.file.flush(),
+            Self::BufferedStdout(_, buffer) => buffer.flush(),
+            Self::Memory(..) => Ok(()),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{OutputStream, WriteMode};
+
+    /// an empty directory for one test
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kay-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("real")).unwrap();
+        dir
+    }
+
+    fn render(path: PathBuf, mode: WriteMode, text: &str, commit: bool) {
+        let mut output = OutputStream::from_file(path, mode).unwrap_or_else(|_| panic!("opening the output failed"));
+        output.write_all(text.as_bytes()).unwrap();
+        if commit {
+            output.commit().unwrap();
+        }
+    }
+
+    #[test]
+    fn symlinks_are_kept() {
+        for (name, mode) in [("buffered", WriteMode::Buffered), ("atomic", WriteMode::Atomic)] {
+            let dir = dir(name);
+            fs::write(dir.join("real/conf"), "old").unwrap();
+            symlink("real/conf", dir.join("link.conf")).unwrap();
+            symlink("real/new", dir.join("dangling")).unwrap();
+            render(dir.join("link.conf"), mode, "new", true);
+            render(dir.join("dangling"), mode, "created", true);
+            assert!(fs::symlink_metadata(dir.join("link.conf")).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(dir.join("real/conf")).unwrap(), "new");
+            assert_eq!(fs::read_to_string(dir.join("real/new")).unwrap(), "created");
+            assert_eq!(fs::read_dir(dir.join("real")).unwrap().count(), 2, "no temporary file is left");
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn nothing_is_written_without_commit() {
+        for (name, mode) in [("uncommitted-buffered", WriteMode::Buffered), ("uncommitted-atomic", WriteMode::Atomic)] {
+            let dir = dir(name);
+            fs::write(dir.join("real/conf"), "old").unwrap();
+            render(dir.join("real/conf"), mode, "partial", false);
+            assert_eq!(fs::read_to_string(dir.join("real/conf")).unwrap(), "old");
+            assert_eq!(fs::read_dir(dir.join("real")).unwrap().count(), 1, "no temporary file is left");
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
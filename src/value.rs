@@ -0,0 +1,127 @@
+use std::fmt;
+
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+
+/// result of evaluating an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// content that is not valid utf8, e.g. a binary file
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub fn from_bytes(bytes: Vec<u8>) -> Value {
+        match String::from_utf8(bytes) {
+            Ok(s) => Value::Str(s),
+            Err(e) => Value::Bytes(e.into_bytes()),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Bytes(b) => !b.is_empty(),
+            Value::List(l) => !l.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+        }
+    }
+
+    /// the bytes written to the output for this value
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Value::Str(s) => s.into_bytes(),
+            Value::Bytes(b) => b,
+            value => value.to_string().into_bytes(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            Value::Null => JsonValue::Null,
+            Value::Bool(b) => JsonValue::Bool(*b),
+            Value::Int(i) => JsonValue::from(*i),
+            Value::Float(f) => JsonValue::from(*f),
+            Value::Str(s) => JsonValue::String(s.clone()),
+            Value::Bytes(b) => JsonValue::String(String::from_utf8_lossy(b).into_owned()),
+            Value::List(l) => JsonValue::Array(l.iter().map(Value::to_json).collect()),
+            Value::Map(m) => JsonValue::Object(
+                m.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_json()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn to_yaml(&self) -> YamlValue {
+        match self {
+            Value::Null => YamlValue::Null,
+            Value::Bool(b) => YamlValue::Bool(*b),
+            Value::Int(i) => YamlValue::Number((*i).into()),
+            Value::Float(f) => YamlValue::Number((*f).into()),
+            Value::Str(s) => YamlValue::String(s.clone()),
+            Value::Bytes(b) => YamlValue::String(String::from_utf8_lossy(b).into_owned()),
+            Value::List(l) => YamlValue::Sequence(l.iter().map(Value::to_yaml).collect()),
+            Value::Map(m) => YamlValue::Mapping(m.iter().map(|(k, v)| (k.to_yaml(), v.to_yaml())).collect()),
+        }
+    }
+}
+
+impl From<&JsonValue> for Value {
+    fn from(json: &JsonValue) -> Value {
+        match json {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(b) => Value::Bool(*b),
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            JsonValue::String(s) => Value::Str(s.clone()),
+            JsonValue::Array(a) => Value::List(a.iter().map(Value::from).collect()),
+            JsonValue::Object(o) => Value::Map(
+                o.iter()
+                    .map(|(k, v)| (Value::Str(k.clone()), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// scalars are written as is, lists and maps as json
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bytes(b) => write!(f, "{}", String::from_utf8_lossy(b)),
+            Value::List(_) | Value::Map(_) => write!(f, "{}", self.to_json()),
+        }
+    }
+}
@@ -1,11 +1,25 @@
+use std::env;
 use std::fmt;
 use std::str;
-use std::io;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::ffi::OsStr;
-use serde_json::Value as JsonValue;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{Map, Value as JsonValue};
+use wait_timeout::ChildExt;
 
-use super::Error;
+use super::parse::{matches, EnvPolicy};
+use super::{Error, InputStream, Value};
+
+mod yaml;
+
+pub use yaml::{Documents, TagPolicy};
+use yaml::Node;
 
 fn file_to_string<'p, 'e: 'p>(path: &'p Path) -> Result<String, Error<'e>> {
     fn internal(path: &Path) -> io::Result<String> {
@@ -29,18 +43,65 @@ where
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum VarsFormat {
     Json,
     Yaml,
+    Toml,
 }
 impl VarsFormat {
     pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<VarsFormat> {
         match path.as_ref().extension().and_then(OsStr::to_str) {
             Some("yaml") | Some("yml") => Some(VarsFormat::Yaml),
             Some("json") => Some(VarsFormat::Json),
+            Some("toml") => Some(VarsFormat::Toml),
             _ => None,
         }
     }
+
+    /// guesses the format of a document: a `{` starts json, a `[table]` or `key = value` line toml, anything else is yaml
+    pub fn sniff(text: &str) -> VarsFormat {
+        let first_line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        let is_key = |key: &str| {
+            let key = key.trim();
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || "_-.\"'".contains(c))
+        };
+        let is_table = first_line.starts_with('[') && first_line.ends_with(']') && !first_line.contains(',');
+        let is_assignment = first_line.contains('=') && first_line.split('=').next().is_some_and(is_key);
+        if first_line.starts_with('{') {
+            VarsFormat::Json
+        } else if is_table || is_assignment {
+            VarsFormat::Toml
+        } else {
+            VarsFormat::Yaml
+        }
+    }
+
+    /// parses a vars document, `policy` restricts what yaml tags read
+    pub fn parse(&self, text: &str, policy: &TagPolicy) -> Result<JsonValue, String> {
+        match self {
+            VarsFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            VarsFormat::Yaml => yaml::load(text, None, Path::new("."), policy).map(|node| node.to_json()),
+            VarsFormat::Toml => text.parse().map(toml_to_json).map_err(|e: toml::de::Error| e.to_string()),
+        }
+    }
+}
+
+/// converts a toml document, dates and times become strings
+fn toml_to_json(value: toml::Value) -> JsonValue {
+    match value {
+        toml::Value::String(s) => JsonValue::String(s),
+        toml::Value::Integer(i) => JsonValue::from(i),
+        toml::Value::Float(f) => JsonValue::from(f),
+        toml::Value::Boolean(b) => JsonValue::Bool(b),
+        toml::Value::Datetime(d) => JsonValue::String(d.to_string()),
+        toml::Value::Array(items) => JsonValue::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => JsonValue::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
 }
 impl str::FromStr for VarsFormat {
     type Err = String;
@@ -48,7 +109,8 @@ impl str::FromStr for VarsFormat {
         match s {
             "json" => Ok(VarsFormat::Json),
             "yaml" => Ok(VarsFormat::Yaml),
-            _ => Err(String::from(r#"only "json" and "yaml" are supported"#)),
+            "toml" => Ok(VarsFormat::Toml),
+            _ => Err(String::from(r#"only "json", "yaml" and "toml" are supported"#)),
         }
     }
 }
@@ -57,6 +119,7 @@ impl fmt::Debug for VarsFormat {
         match self {
             VarsFormat::Json => write!(f, "json"),
             VarsFormat::Yaml => write!(f, "yaml"),
+            VarsFormat::Toml => write!(f, "toml"),
         }
     }
 }
@@ -65,7 +128,6 @@ pub enum VarsError {
     NotFound(String),
     InvalidSelector(String, Box<dyn std::fmt::Display>),
     MultipleResults(String),
-    StringConv(String),
 }
 
 pub struct JsonVars {
@@ -73,27 +135,153 @@ pub struct JsonVars {
 }
 
 pub trait Vars {
-    fn get<'a>(&'a self, path: &'a str) -> Result<&'a str, VarsError> {
+    fn get(&self, path: &str) -> Result<Value, VarsError> {
         Err(VarsError::NotFound(path.into()))
     }
 }
 impl Vars for JsonVars {
-    fn get<'a>(&'a self, path: &'a str) -> Result<&'a str, VarsError> {
+    fn get(&self, path: &str) -> Result<Value, VarsError> {
+        self.select(path).map(Value::from)
+    }
+}
+
+/// vars other documents can be merged into, see `--vars-exec` and `--env-vars`
+pub trait Document: Vars {
+    fn json_vars(&mut self) -> &mut JsonVars;
+}
+impl Document for JsonVars {
+    fn json_vars(&mut self) -> &mut JsonVars {
+        self
+    }
+}
+
+/// reads the vars from stdin, in `format` or the one they look like
+pub fn from_stdin<'a>(
+    stdin: &'a io::Stdin,
+    format: Option<VarsFormat>,
+    documents: Option<Documents>,
+    policy: &TagPolicy,
+) -> Result<Box<dyn Document>, Error<'a>> {
+    let mut buffer = String::new();
+    if let Err(e) = stdin.lock().read_to_string(&mut buffer) {
+        return Err(Error::Input(InputStream::from_stdin(stdin), e));
+    }
+    parse_stdin(&buffer, format, documents, policy)
+}
+
+/// parses the vars read from stdin
+fn parse_stdin<'a>(
+    buffer: &str,
+    format: Option<VarsFormat>,
+    documents: Option<Documents>,
+    policy: &TagPolicy,
+) -> Result<Box<dyn Document>, Error<'a>> {
+    let path = PathBuf::from("-");
+    Ok(match format.unwrap_or_else(|| VarsFormat::sniff(buffer)) {
+        VarsFormat::Json => Box::new(JsonVars {
+            json: serde_json::from_str(buffer).map_err(|e| Error::JsonParseVars(path, e))?,
+        }),
+        VarsFormat::Yaml => Box::new(YamlVars::new(
+            yaml::load(buffer, documents, Path::new("."), policy).map_err(|e| Error::YamlParseVars(path, e))?,
+        )),
+        VarsFormat::Toml => Box::new(JsonVars {
+            json: toml_to_json(buffer.parse().map_err(|e| Error::TomlParseVars(path, e))?),
+        }),
+    })
+}
+/// how `mount_env` turns environment variables into values
+pub struct EnvMount<'a> {
+    /// key of the map holding the variables
+    pub key: &'a str,
+    /// the variables that may be mounted
+    pub policy: &'a EnvPolicy,
+    /// numbers and booleans become typed values instead of strings
+    pub coerce: bool,
+    /// patterns of the variables split on `:` into lists, such as `PATH`
+    pub split: &'a [String],
+}
+
+/// a typed value for strings such as `8080`, `0.5` or `true`, leading zeros keep a string
+fn coerce(value: String) -> JsonValue {
+    let digits = value.trim_start_matches('-');
+    if digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.") {
+        return JsonValue::String(value);
+    }
+    if let Ok(boolean) = value.parse::<bool>() {
+        return JsonValue::Bool(boolean);
+    }
+    if let Ok(int) = value.parse::<i64>() {
+        return JsonValue::from(int);
+    }
+    // rules out `inf`, `nan` and the like
+    if value.chars().all(|c| c.is_ascii_digit() || ".eE+-".contains(c)) {
+        if let Ok(number) = value.parse::<f64>().map(JsonValue::from) {
+            if number.is_f64() {
+                return number;
+            }
+        }
+    }
+    JsonValue::String(value)
+}
+
+impl JsonVars {
+    pub fn new() -> Self {
+        JsonVars {
+            json: JsonValue::Object(Map::new()),
+        }
+    }
+
+    /// the single value `path` selects
+    fn select<'s>(&'s self, path: &'s str) -> Result<&'s JsonValue, VarsError> {
         let mut selector = jsonpath_lib::selector(&self.json);
         let results = selector(path).map_err(|e| VarsError::InvalidSelector(path.into(), Box::new(e)))?;
-        if results.len() == 0 {
+        if results.is_empty() {
             Err(VarsError::NotFound(path.into()))
         } else if results.len() > 1 {
             Err(VarsError::MultipleResults(path.into()))
         } else {
-            match results.first().unwrap().as_str() {
-                None => Err(VarsError::StringConv(path.into())),
-                Some(val) => Ok(val)
+            Ok(results[0])
+        }
+    }
+
+    /// merges another document into the vars, maps are merged key by key, later values win
+    pub fn merge<'a>(&mut self, other: JsonValue, command: &str) -> Result<(), Error<'a>> {
+        if !self.json.is_object() || !other.is_object() {
+            return Err(Error::InvalidVarsMerge(command.into()));
+        }
+        merge(&mut self.json, other);
+        Ok(())
+    }
+
+    /// adds the environment variables to the map at `$.KEY`, replacing vars of the same name
+    pub fn mount_env<'a>(&mut self, mount: &EnvMount) -> Result<(), Error<'a>> {
+        let invalid = || Error::InvalidEnvMount(mount.key.into());
+        let map = self
+            .json
+            .as_object_mut()
+            .ok_or_else(invalid)?
+            .entry(mount.key)
+            .or_insert_with(|| JsonValue::Object(Map::new()))
+            .as_object_mut()
+            .ok_or_else(invalid)?;
+        for (name, value) in env::vars_os() {
+            let name = name.to_string_lossy().into_owned();
+            if !mount.policy.is_allowed(&name) {
+                continue;
             }
+            let value = value.to_string_lossy().into_owned();
+            let typed = |value: String| if mount.coerce { coerce(value) } else { JsonValue::String(value) };
+            let value = if mount.split.iter().any(|pattern| matches(pattern, &name)) {
+                let items = value.split(':').filter(|item| !item.is_empty()).map(|item| typed(item.into()));
+                JsonValue::Array(items.collect())
+            } else {
+                typed(value)
+            };
+            map.insert(name, value);
         }
+        Ok(())
     }
-}
-impl JsonVars {
+
     pub fn from_file<'a, P: AsRef<Path> + 'a>(path: P) -> Result<Self, Error<'a>> {
         let path = path.as_ref();
         let buffer = file_to_string(path)?;
@@ -104,30 +292,149 @@ impl JsonVars {
     }
 }
 
-pub struct YamlVars {}
+fn merge(value: &mut JsonValue, other: JsonValue) {
+    match (value, other) {
+        (JsonValue::Object(map), JsonValue::Object(other)) => {
+            for (key, other) in other {
+                match map.get_mut(&key) {
+                    Some(value) => merge(value, other),
+                    None => {
+                        map.insert(key, other);
+                    }
+                }
+            }
+        }
+        (value, other) => *value = other,
+    }
+}
+
+/// runs a shell command and parses its output, in `format` or the one it looks like
+pub fn exec_vars<'a>(
+    command: &str,
+    format: Option<VarsFormat>,
+    timeout: Duration,
+    policy: &TagPolicy,
+) -> Result<JsonValue, Error<'a>> {
+    let deadline = Instant::now() + timeout;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        // the template may come from stdin, the command's errors are shown as they are
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        // a group of its own, so that a timeout also kills what the command started
+        .process_group(0)
+        .spawn()
+        .map_err(|e| Error::ExecVars(command.into(), e))?;
+    // read while the command runs, so that it does not block on a full pipe
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+    let timed_out = |child: &mut Child| {
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        let _ = child.wait();
+        let reason = format!("did not finish within {}s", timeout.as_secs());
+        Error::ExecVarsFailed(command.into(), reason)
+    };
+    let status = match child.wait_timeout(timeout).map_err(|e| Error::ExecVars(command.into(), e))? {
+        Some(status) => status,
+        None => return Err(timed_out(&mut child)),
+    };
+    if !status.success() {
+        return Err(Error::ExecVarsFailed(command.into(), status.to_string()));
+    }
+    // a process the command left in the background may still hold the output open
+    let output = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output.map_err(|e| Error::ExecVars(command.into(), e))?,
+        Err(_) => return Err(timed_out(&mut child)),
+    };
+    let output = String::from_utf8_lossy(&output);
+    let format = format.unwrap_or_else(|| VarsFormat::sniff(&output));
+    format
+        .parse(&output, policy)
+        .map_err(|message| Error::ExecVarsParse(command.into(), format, message))
+}
+
+/// yaml vars, values are rendered as they were written: key order, typed keys and scalars such as `0755` are kept
+pub struct YamlVars {
+    root: Node,
+    /// selectors run on the json form of the document, which merged documents are added to
+    vars: JsonVars,
+}
 impl Vars for YamlVars {
-    fn get<'a>(&'a self, path: &'a str) -> Result<&'a str, VarsError> {
-        Err(VarsError::NotFound(path.into()))
+    fn get(&self, path: &str) -> Result<Value, VarsError> {
+        let result = self.vars.select(path)?;
+        // values added or changed by a merge only exist in the json
+        Ok(match find(&self.root, &self.vars.json, result) {
+            Some(node) if node.to_json() == *result => node.to_value(),
+            _ => Value::from(result),
+        })
+    }
+}
+impl Document for YamlVars {
+    fn json_vars(&mut self) -> &mut JsonVars {
+        &mut self.vars
+    }
+}
+
+/// the node `target` was converted from, `json` being the conversion of `node`
+fn find<'n>(node: &'n Node, json: &JsonValue, target: &JsonValue) -> Option<&'n Node> {
+    if ptr::eq(json, target) {
+        return Some(node);
+    }
+    match (node, json) {
+        (Node::Seq(items), JsonValue::Array(values)) => {
+            items.iter().zip(values).find_map(|(node, json)| find(node, json, target))
+        }
+        (Node::Map(entries), JsonValue::Object(map)) => entries
+            .iter()
+            .find_map(|(key, node)| map.get(&key.to_key()).and_then(|json| find(node, json, target))),
+        _ => None,
     }
 }
 
 impl YamlVars {
-    pub fn from_file<'a, P: AsRef<Path> + 'a>(path: P) -> Result<JsonVars, Error<'a>> {
+    fn new(root: Node) -> Self {
+        let json = root.to_json();
+        YamlVars {
+            root,
+            vars: JsonVars { json },
+        }
+    }
+
+    pub fn from_file<'a, P: AsRef<Path> + 'a>(
+        path: P,
+        documents: Option<Documents>,
+        policy: &TagPolicy,
+    ) -> Result<Self, Error<'a>> {
         let path = path.as_ref();
         let buffer = file_to_string(path)?;
-        // let yaml = StrictYamlLoader::load_from_str(&buffer)
-        //     .map_err(|yaml_error| Error::YamlParseVars(path.into(), yaml_error));
-        let json = serde_yaml::from_str::<serde_json::Value>(&buffer)
-            .map_err(|json_error| Error::YamlParseVars(path.into(), json_error))?;
-        Ok(JsonVars {
-            json
-        })
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let root = yaml::load(&buffer, documents, dir, policy)
+            .map_err(|yaml_error| Error::YamlParseVars(path.into(), yaml_error))?;
+        Ok(YamlVars::new(root))
     }
 }
 
+/// reads a toml vars file, they are json vars once parsed
+pub fn from_toml_file<'a, P: AsRef<Path> + 'a>(path: P) -> Result<JsonVars, Error<'a>> {
+    let path = path.as_ref();
+    let buffer = file_to_string(path)?;
+    let toml = buffer
+        .parse::<toml::Value>()
+        .map_err(|toml_error| Error::TomlParseVars(path.into(), toml_error))?;
+    Ok(JsonVars {
+        json: toml_to_json(toml),
+    })
+}
+
 pub struct EmptyVars {}
 impl Vars for EmptyVars {
-    fn get<'a>(&'a self, path: &'a str) -> Result<&'a str, VarsError> {
+    fn get(&self, path: &str) -> Result<Value, VarsError> {
         Err(VarsError::NotFound(path.into()))
     }
 }
@@ -136,3 +443,145 @@ impl EmptyVars {
         EmptyVars {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use super::{coerce, exec_vars, parse_stdin, Documents, EnvMount, EnvPolicy, JsonVars, TagPolicy, VarsFormat};
+    use crate::parse::testing::lock_env;
+    use crate::parse::FilePolicy;
+
+    #[test]
+    fn sniff() {
+        let sniff = |text| format!("{:?}", VarsFormat::sniff(text));
+        assert_eq!(sniff(" {\"a\": 1}"), "json");
+        assert_eq!(sniff("# vars\n\n[server]\nport = 80\n"), "toml");
+        assert_eq!(sniff("port = 80"), "toml");
+        assert_eq!(sniff("\"quoted.key\" = 1"), "toml");
+        assert_eq!(sniff("port: 80"), "yaml");
+        assert_eq!(sniff("[1, 2]"), "yaml");
+        assert_eq!(sniff("- a = b"), "yaml");
+        assert_eq!(sniff("url: http://host/?a=b"), "yaml");
+        assert_eq!(sniff(""), "yaml");
+    }
+
+    fn exec(command: &str, format: Option<VarsFormat>) -> Result<String, String> {
+        let env = EnvPolicy::new(vec![], vec![], true);
+        let files = FilePolicy::new(None, vec![], PathBuf::from("."));
+        exec_vars(command, format, Duration::from_secs(1), &TagPolicy { env: &env, files: &files })
+            .map(|json| json.to_string())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(exec("echo '{\"a\": 1}'", None).unwrap(), r#"{"a":1}"#);
+        assert_eq!(exec("echo a = 1", None).unwrap(), r#"{"a":1}"#);
+        assert_eq!(exec("echo a: 1", None).unwrap(), r#"{"a":1}"#);
+        assert_eq!(exec("echo a: 1", Some(VarsFormat::Yaml)).unwrap(), r#"{"a":1}"#);
+        assert!(exec("echo a: 1", Some(VarsFormat::Json)).unwrap_err().contains("as json"));
+        assert!(exec("echo '{'", None).unwrap_err().starts_with("Unable to parse the output"));
+        let failed = exec("echo '{}'; exit 3", None).unwrap_err();
+        assert!(failed.contains("failed: exit status: 3"), "{}", failed);
+    }
+
+    #[test]
+    fn timeouts() {
+        let start = Instant::now();
+        assert!(exec("sleep 10", None).unwrap_err().ends_with("did not finish within 1s"));
+        // the command is done, a process it left in the background still holds its output
+        assert!(exec("sleep 10 & echo '{}'", None).unwrap_err().ends_with("did not finish within 1s"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn coercion() {
+        assert_eq!(coerce("1".into()), json!(1));
+        assert_eq!(coerce("-12".into()), json!(-12));
+        assert_eq!(coerce("true".into()), json!(true));
+        assert_eq!(coerce("false".into()), json!(false));
+        assert_eq!(coerce("1.5".into()), json!(1.5));
+        assert_eq!(coerce("0.5".into()), json!(0.5));
+        assert_eq!(coerce("1e3".into()), json!(1000.0));
+        assert_eq!(coerce("0".into()), json!(0));
+        // strings that only look like numbers
+        assert_eq!(coerce("0755".into()), json!("0755"));
+        assert_eq!(coerce("-007".into()), json!("-007"));
+        assert_eq!(coerce("".into()), json!(""));
+        assert_eq!(coerce("True".into()), json!("True"));
+        assert_eq!(coerce("inf".into()), json!("inf"));
+        assert_eq!(coerce("NaN".into()), json!("NaN"));
+        assert_eq!(coerce("1.2.3".into()), json!("1.2.3"));
+        assert_eq!(coerce("99999999999999999999".into()), json!(1e20));
+    }
+
+    #[test]
+    fn env_mounts() {
+        let _env = lock_env();
+        std::env::set_var("KAY_MOUNT_PORT", "8080");
+        std::env::set_var("KAY_MOUNT_DEBUG", "true");
+        std::env::set_var("KAY_MOUNT_MODE", "0755");
+        std::env::set_var("KAY_MOUNT_PATH", "/bin::/usr/bin:");
+        std::env::set_var("KAY_MOUNT_SECRET", "s3cr3t");
+        let policy = EnvPolicy::new(vec!["KAY_MOUNT_*".into()], vec!["KAY_MOUNT_SECRET".into()], true);
+        let split = vec![String::from("*_PATH")];
+        let mount = |vars: &mut JsonVars, coerce| {
+            let mount = EnvMount { key: "env", policy: &policy, coerce, split: &split };
+            vars.mount_env(&mount).map_err(|error| error.to_string())
+        };
+
+        let mut vars = JsonVars { json: json!({"env": {"KAY_MOUNT_PORT": "1", "OTHER": 1}, "name": "kay"}) };
+        mount(&mut vars, true).unwrap();
+        let expected = json!({
+            "env": {
+                "KAY_MOUNT_PORT": 8080,
+                "OTHER": 1,
+                "KAY_MOUNT_DEBUG": true,
+                "KAY_MOUNT_MODE": "0755",
+                "KAY_MOUNT_PATH": ["/bin", "/usr/bin"],
+            },
+            "name": "kay",
+        });
+        assert_eq!(vars.json, expected);
+        assert_eq!(vars.select("$.env.KAY_MOUNT_PATH[1]").ok(), Some(&json!("/usr/bin")));
+
+        let mut vars = JsonVars::new();
+        mount(&mut vars, false).unwrap();
+        assert_eq!(vars.json["env"]["KAY_MOUNT_PORT"], json!("8080"));
+        assert_eq!(vars.json["env"]["KAY_MOUNT_PATH"], json!(["/bin", "/usr/bin"]));
+        assert_eq!(vars.json["env"].as_object().unwrap().len(), 4);
+
+        let mut vars = JsonVars { json: json!({"env": "taken"}) };
+        assert!(mount(&mut vars, true).is_err());
+        let mut vars = JsonVars { json: json!([1]) };
+        assert!(mount(&mut vars, true).is_err());
+    }
+
+    #[test]
+    fn stdin() {
+        let env = EnvPolicy::new(vec![], vec![], true);
+        let files = FilePolicy::new(None, vec![], PathBuf::from("."));
+        let policy = TagPolicy { env: &env, files: &files };
+        let get = |text: &str, format, documents, path: &str| match parse_stdin(text, format, documents, &policy) {
+            Ok(vars) => vars.get(path).map(|value| value.to_string()).map_err(|_| String::from("not found")),
+            Err(error) => Err(error.to_string()),
+        };
+        assert_eq!(get(r#"{"a": [1, 2]}"#, None, None, "$.a[1]").unwrap(), "2");
+        assert_eq!(get("[a]\nb = 1\n", None, None, "$.a.b").unwrap(), "1");
+        // yaml values render as they were written
+        assert_eq!(get("mode: 0755\n", None, None, "$.mode").unwrap(), "0755");
+        assert_eq!(get("a: 1\n---\na: 2\n", None, Some(Documents::Index(1)), "$.a").unwrap(), "2");
+        assert_eq!(get("a = 1", Some(VarsFormat::Yaml), None, "$").unwrap(), "a = 1");
+        assert_eq!(get("{}", None, None, "$.a").unwrap_err(), "not found");
+        let error = get(r#"{"a": }"#, None, None, "$").unwrap_err();
+        assert!(error.starts_with(r#"Unable to parse json vars file "-""#), "{}", error);
+        let error = get("a: [", None, None, "$").unwrap_err();
+        assert!(error.starts_with(r#"Unable to parse yaml vars file "-""#), "{}", error);
+        let error = get("[a", Some(VarsFormat::Toml), None, "$").unwrap_err();
+        assert!(error.starts_with(r#"Unable to parse toml vars file "-""#), "{}", error);
+    }
+}
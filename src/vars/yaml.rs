@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::str;
+
+use serde_json::{Map, Value as JsonValue};
+use yaml_rust::parser::{MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle, TokenType};
+use yaml_rust::{Event, Yaml};
+
+use super::super::parse::{EnvPolicy, ExprInternalError, FilePolicy};
+use super::Value;
+
+/// which documents of a multi-document yaml vars file are used
+#[derive(Debug, Clone, Copy)]
+pub enum Documents {
+    Index(usize),
+    /// maps are merged key by key, later documents win
+    Merge,
+    /// an array of every document
+    List,
+}
+
+impl str::FromStr for Documents {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(Documents::Merge),
+            "list" => Ok(Documents::List),
+            _ => s
+                .parse()
+                .map(Documents::Index)
+                .map_err(|_| String::from(r#"expected a document index, "merge" or "list""#)),
+        }
+    }
+}
+
+/// what kay's tags may read, the same restrictions as for templates
+pub struct TagPolicy<'a> {
+    pub env: &'a EnvPolicy,
+    pub files: &'a FilePolicy,
+}
+
+/// a yaml node, scalars keep the text they were written as
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// the typed value and the text of a scalar
+    Scalar(Value, String),
+    Seq(Vec<Node>),
+    /// entries in the order they were written, keys may be any node
+    Map(Vec<(Node, Node)>),
+}
+
+impl Node {
+    fn null() -> Node {
+        Node::Scalar(Value::Null, String::from("~"))
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self, Node::Scalar(Value::Null, _))
+    }
+
+    /// keys of yaml maps may be any scalar, json keys are strings
+    pub fn to_key(&self) -> String {
+        match self {
+            Node::Scalar(Value::Null, _) => String::from("null"),
+            Node::Scalar(Value::Float(_), text) => text.clone(),
+            Node::Scalar(value, _) => value.to_string(),
+            key => key.to_json().to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            Node::Scalar(value, _) => value.to_json(),
+            Node::Seq(items) => JsonValue::Array(items.iter().map(Node::to_json).collect()),
+            Node::Map(entries) => JsonValue::Object(entries.iter().map(|(k, v)| (k.to_key(), v.to_json())).collect::<Map<_, _>>()),
+        }
+    }
+
+    /// the value as it was written: a scalar such as `0755` or `1.10` keeps its text, keys keep their type
+    pub fn to_value(&self) -> Value {
+        match self {
+            Node::Scalar(value @ Value::Null, _) | Node::Scalar(value @ Value::Str(_), _) => value.clone(),
+            Node::Scalar(value, text) if value.to_string() == *text => value.clone(),
+            Node::Scalar(_, text) => Value::Str(text.clone()),
+            Node::Seq(items) => Value::List(items.iter().map(Node::to_value).collect()),
+            Node::Map(entries) => Value::Map(entries.iter().map(|(k, v)| (k.to_value(), v.to_value())).collect()),
+        }
+    }
+}
+
+/// the value of a plain scalar, like `yaml_rust::Yaml::from_str`
+fn plain_value(text: &str) -> Value {
+    match Yaml::from_str(text) {
+        Yaml::Integer(i) => Value::Int(i),
+        Yaml::Real(s) => s.parse().map_or(Value::Null, Value::Float),
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::String(s) => Value::Str(s),
+        _ => Value::Null,
+    }
+}
+
+/// the value of a `!!` core schema tag, like `yaml_rust::YamlLoader`
+fn core_tag(suffix: &str, text: String) -> Node {
+    let value = match (suffix, plain_value(&text)) {
+        ("bool", value @ Value::Bool(_))
+        | ("int", value @ Value::Int(_))
+        | ("float", value @ Value::Float(_))
+        | ("null", value @ Value::Null) => value,
+        ("float", Value::Int(i)) => Value::Float(i as f64),
+        ("bool", _) | ("int", _) | ("float", _) | ("null", _) => Value::Null,
+        _ => Value::Str(text.clone()),
+    };
+    Node::Scalar(value, text)
+}
+
+/// the value of one of kay's tags: `!env NAME`, `!file path` or `!base64 text`
+fn kay_tag(tag: &str, value: &str, dir: &Path, policy: &TagPolicy) -> Result<String, String> {
+    match tag {
+        "env" if !policy.env.is_allowed(value) => Err(format!(
+            r#"reading environment variable "{}" is not allowed, see --env-allow, --env-deny and --no-env"#,
+            value
+        )),
+        "env" => env::var(value).map_err(|e| match e {
+            env::VarError::NotPresent => format!(r#"environment variable "{}" is not defined"#, value),
+            e => format!(r#"environment variable "{}" {}"#, value, e),
+        }),
+        "file" => {
+            let bytes = policy.files.read(value, dir).map_err(|e| match e {
+                ExprInternalError::FileAccessDenied(path) => {
+                    format!(r#"reading file "{}" is not allowed, see --file-allow"#, path.display())
+                }
+                ExprInternalError::FileRead(path, e) => format!(r#"unable to read file "{}": {}"#, path.display(), e),
+                _ => unreachable!("reading a file only fails when it is denied or unreadable"),
+            })?;
+            String::from_utf8(bytes).map_err(|_| String::from("the file is not utf8 text"))
+        }
+        "base64" => {
+            let bytes = base64::decode(value.trim()).map_err(|e| e.to_string())?;
+            String::from_utf8(bytes).map_err(|_| String::from("the decoded value is not utf8 text"))
+        }
+        tag => Err(format!("unknown tag !{}, kay knows !env, !file and !base64", tag)),
+    }
+}
+
+/// sets the value of `key`, a repeated key keeps its first position
+fn insert_entry(entries: &mut Vec<(Node, Node)>, key: Node, value: Node) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, v)) => *v = value,
+        None => entries.push((key, value)),
+    }
+}
+
+fn contains_key(entries: &[(Node, Node)], key: &Node) -> bool {
+    entries.iter().any(|(k, _)| k == key)
+}
+
+/// applies `<<` merge keys: the entries of the merged map, or of every map of a merged list,
+/// are added where the key stood unless the mapping sets them itself, earlier maps win
+fn resolve_merge_keys(entries: Vec<(Node, Node)>) -> Result<Vec<(Node, Node)>, String> {
+    let is_merge_key = |key: &Node| matches!(key, Node::Scalar(Value::Str(s), _) if s == "<<");
+    if !entries.iter().any(|(key, _)| is_merge_key(key)) {
+        return Ok(entries);
+    }
+    let mut resolved = Vec::new();
+    for (key, value) in &entries {
+        if !is_merge_key(key) {
+            resolved.push((key.clone(), value.clone()));
+            continue;
+        }
+        let invalid = || String::from("a merge key `<<` takes a map or a list of maps");
+        let sources = match value {
+            Node::Map(source) => vec![source],
+            Node::Seq(items) => items
+                .iter()
+                .map(|item| match item {
+                    Node::Map(source) => Ok(source),
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(invalid()),
+        };
+        for source in sources {
+            for (key, value) in source {
+                if !contains_key(&entries, key) && !contains_key(&resolved, key) {
+                    resolved.push((key.clone(), value.clone()));
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// merges the maps of two documents key by key, later values win
+fn merge(node: &mut Node, other: Node) {
+    match (node, other) {
+        (Node::Map(entries), Node::Map(other)) => {
+            for (key, other) in other {
+                match entries.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, value)) => merge(value, other),
+                    None => entries.push((key, other)),
+                }
+            }
+        }
+        (node, other) => *node = other,
+    }
+}
+
+/// builds the documents like `yaml_rust::YamlLoader`, additionally resolving merge keys and kay's tags
+struct Loader<'a> {
+    docs: Vec<Node>,
+    /// the nodes being built with their anchor id
+    doc_stack: Vec<(Node, usize)>,
+    /// the key of every mapping being built, `None` until it is read
+    key_stack: Vec<Option<Node>>,
+    anchors: BTreeMap<usize, Node>,
+    /// directory `!file` paths are relative to
+    dir: &'a Path,
+    policy: &'a TagPolicy<'a>,
+    /// the first error, the parser keeps sending events
+    error: Option<String>,
+}
+
+impl<'a> Loader<'a> {
+    fn fail(&mut self, message: String, mark: Marker) {
+        if self.error.is_none() {
+            self.error = Some(format!("{} at line {} column {}", message, mark.line(), mark.col() + 1));
+        }
+    }
+
+    fn insert(&mut self, node: Node, anchor: usize) {
+        // anchor ids start at 1
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.doc_stack.last_mut() {
+            None => self.doc_stack.push((node, anchor)),
+            Some((Node::Seq(items), _)) => items.push(node),
+            Some((Node::Map(entries), _)) => {
+                let key = self.key_stack.last_mut().expect("every mapping has a key");
+                match key.take() {
+                    None => *key = Some(node),
+                    Some(key) => insert_entry(entries, key, node),
+                }
+            }
+            Some(_) => unreachable!(),
+        }
+    }
+}
+
+impl<'a> MarkedEventReceiver for Loader<'a> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::DocumentEnd => {
+                let doc = self.doc_stack.pop().map_or_else(Node::null, |(node, _)| node);
+                self.docs.push(doc);
+            }
+            Event::SequenceStart(anchor) => self.doc_stack.push((Node::Seq(Vec::new()), anchor)),
+            Event::MappingStart(anchor) => {
+                self.doc_stack.push((Node::Map(Vec::new()), anchor));
+                self.key_stack.push(None);
+            }
+            Event::SequenceEnd => {
+                let (node, anchor) = self.doc_stack.pop().expect("a sequence was started");
+                self.insert(node, anchor);
+            }
+            Event::MappingEnd => {
+                self.key_stack.pop();
+                let (node, anchor) = self.doc_stack.pop().expect("a mapping was started");
+                let node = match node {
+                    Node::Map(entries) => resolve_merge_keys(entries).map(Node::Map).unwrap_or_else(|message| {
+                        self.fail(message, mark);
+                        Node::null()
+                    }),
+                    node => node,
+                };
+                self.insert(node, anchor);
+            }
+            Event::Scalar(value, style, anchor, tag) => {
+                let node = match tag {
+                    Some(TokenType::Tag(ref handle, ref suffix)) if handle == "!" => {
+                        match kay_tag(suffix, &value, self.dir, self.policy) {
+                            Ok(text) => Node::Scalar(Value::Str(text.clone()), text),
+                            Err(message) => {
+                                self.fail(format!("!{} {}: {}", suffix, value, message), mark);
+                                Node::null()
+                            }
+                        }
+                    }
+                    Some(TokenType::Tag(ref handle, ref suffix)) if handle == "!!" && style == TScalarStyle::Plain => {
+                        core_tag(suffix, value)
+                    }
+                    _ if style != TScalarStyle::Plain => Node::Scalar(Value::Str(value.clone()), value),
+                    _ => Node::Scalar(plain_value(&value), value),
+                };
+                self.insert(node, anchor);
+            }
+            Event::Alias(id) => {
+                let node = self.anchors.get(&id).cloned().unwrap_or_else(Node::null);
+                self.insert(node, 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// parses yaml vars, a file holding several documents needs `documents` to tell which are used,
+/// `!file` paths are relative to `dir`, `policy` restricts what the tags read
+pub fn load(text: &str, documents: Option<Documents>, dir: &Path, policy: &TagPolicy) -> Result<Node, String> {
+    let mut loader = Loader {
+        docs: Vec::new(),
+        doc_stack: Vec::new(),
+        key_stack: Vec::new(),
+        anchors: BTreeMap::new(),
+        dir,
+        policy,
+        error: None,
+    };
+    Parser::new(text.chars()).load(&mut loader, true).map_err(|e| e.to_string())?;
+    if let Some(error) = loader.error {
+        return Err(error);
+    }
+    let mut docs = loader.docs;
+    let count = docs.len();
+    match documents {
+        None if count > 1 => Err(format!(
+            "it holds {} documents, choose one with --vars-documents INDEX or combine them with --vars-documents merge / list",
+            count
+        )),
+        None => Ok(docs.pop().unwrap_or_else(Node::null)),
+        Some(Documents::Index(index)) if index < count => Ok(docs.swap_remove(index)),
+        Some(Documents::Index(index)) => Err(format!("there is no document {}, it holds {} (counting from 0)", index, count)),
+        Some(Documents::Merge) => Ok(docs
+            .into_iter()
+            // empty documents do not remove what earlier ones hold
+            .filter(|doc| !doc.is_null())
+            .fold(Node::null(), |mut merged, doc| {
+                merge(&mut merged, doc);
+                merged
+            })),
+        Some(Documents::List) => Ok(Node::Seq(docs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{load, Documents, EnvPolicy, FilePolicy, Node, TagPolicy};
+    use crate::parse::testing::lock_env;
+    use crate::value::Value;
+
+    fn load_with(text: &str, documents: Option<Documents>, env: &EnvPolicy) -> Result<Node, String> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let files = FilePolicy::new(None, vec![dir.join("src")], PathBuf::from(dir));
+        load(text, documents, dir, &TagPolicy { env, files: &files })
+    }
+
+    fn json(text: &str) -> String {
+        load_with(text, None, &EnvPolicy::new(vec![], vec![], true)).unwrap().to_json().to_string()
+    }
+
+    #[test]
+    fn merge_keys() {
+        let text = "defaults: &defaults\n  a: 1\n  b: 2\nprod:\n  <<: *defaults\n  b: 3\n";
+        assert_eq!(json(text), r#"{"defaults":{"a":1,"b":2},"prod":{"a":1,"b":3}}"#);
+        let text = "x: &x {a: 1}\ny: &y {a: 2, b: 2}\nz:\n  <<: [*x, *y]\n";
+        assert_eq!(json(text), r#"{"x":{"a":1},"y":{"a":2,"b":2},"z":{"a":1,"b":2}}"#);
+        assert!(load_with("a:\n  <<: 1\n", None, &EnvPolicy::new(vec![], vec![], true)).is_err());
+    }
+
+    #[test]
+    fn documents() {
+        let env = EnvPolicy::new(vec![], vec![], true);
+        let text = "a: 1\nb: 1\n---\nb: 2\n";
+        assert!(load_with(text, None, &env).unwrap_err().contains("2 documents"));
+        let load = |documents| load_with(text, Some(documents), &env).map(|node| node.to_json().to_string());
+        assert_eq!(load(Documents::Index(1)).unwrap(), r#"{"b":2}"#);
+        assert_eq!(load(Documents::Merge).unwrap(), r#"{"a":1,"b":2}"#);
+        assert_eq!(load(Documents::List).unwrap(), r#"[{"a":1,"b":1},{"b":2}]"#);
+        assert!(load(Documents::Index(2)).is_err());
+    }
+
+    #[test]
+    fn scalars_keep_their_text() {
+        let node = load_with("mode: 0755\nversion: 1.10\nquoted: '1.10'\n", None, &EnvPolicy::new(vec![], vec![], true));
+        match node.unwrap().to_value() {
+            Value::Map(entries) => {
+                let values = entries.into_iter().map(|(_, v)| v.to_string()).collect::<Vec<_>>();
+                assert_eq!(values, ["0755", "1.10", "1.10"]);
+            }
+            value => panic!("not a map: {:?}", value),
+        }
+        assert_eq!(json("a: !!str 1\nb: !!int 2\n"), r#"{"a":"1","b":2}"#);
+    }
+
+    #[test]
+    fn tags() {
+        let _env = lock_env();
+        std::env::set_var("KAY_YAML_TEST", "from env");
+        assert_eq!(json("a: !env KAY_YAML_TEST\nb: !base64 aGk=\n"), r#"{"a":"from env","b":"hi"}"#);
+        let text = json("a: !file src/vars/yaml.rs\n");
+        assert!(text.contains("kay's tags"));
+        let env = EnvPolicy::new(vec![], vec![], true);
+        assert!(load_with("a: !nope x\n", None, &env).unwrap_err().contains("unknown tag"));
+        assert!(load_with("a: !env KAY_YAML_TEST_UNSET\n", None, &env).unwrap_err().contains("not defined"));
+    }
+
+    #[test]
+    fn tags_follow_the_policies() {
+        let _env = lock_env();
+        std::env::set_var("KAY_YAML_SECRET", "s");
+        let denied = |env: &EnvPolicy| load_with("a: !env KAY_YAML_SECRET\n", None, env).unwrap_err();
+        assert!(denied(&EnvPolicy::new(vec![], vec![], false)).contains("not allowed"));
+        assert!(denied(&EnvPolicy::new(vec![], vec![String::from("KAY_YAML_S*")], true)).contains("not allowed"));
+        assert!(denied(&EnvPolicy::new(vec![String::from("HOME")], vec![], true)).contains("not allowed"));
+        let env = EnvPolicy::new(vec![], vec![], true);
+        assert!(load_with("a: !file Cargo.toml\n", None, &env).unwrap_err().contains("not allowed"));
+        assert!(load_with("a: !file src/../Cargo.toml\n", None, &env).unwrap_err().contains("not allowed"));
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// events of a directory that may change one of the files in it,
+/// editors often replace a file instead of writing to it
+const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_CREATE | libc::IN_DELETE;
+
+/// waits for changes of a set of files using inotify on their directories
+pub struct Watcher {
+    fd: libc::c_int,
+    /// watch descriptors of the directories
+    dirs: HashMap<PathBuf, libc::c_int>,
+    /// watch descriptor of the directory and name of every watched file
+    files: HashSet<(libc::c_int, OsString)>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd,
+            dirs: HashMap::new(),
+            files: HashSet::new(),
+        })
+    }
+
+    /// replaces the watched files, returns the directories that could not be watched
+    pub fn watch<P: AsRef<Path>>(&mut self, files: &[P]) -> Vec<(PathBuf, io::Error)> {
+        for (_, wd) in self.dirs.drain() {
+            unsafe { libc::inotify_rm_watch(self.fd, wd) };
+        }
+        self.files.clear();
+        let mut failed = Vec::new();
+        for file in files {
+            let (dir, name) = match existing_parent(file.as_ref()) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let wd = match self.dirs.get(dir) {
+                Some(&wd) => wd,
+                None => match self.add_watch(dir) {
+                    Ok(wd) => wd,
+                    Err(error) => {
+                        failed.push((dir.into(), error));
+                        continue;
+                    }
+                },
+            };
+            self.files.insert((wd, name.into()));
+        }
+        failed
+    }
+
+    fn add_watch(&mut self, dir: &Path) -> io::Result<libc::c_int> {
+        let path = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(dir.into(), wd);
+        Ok(wd)
+    }
+
+    /// blocks until a watched file changed and no further event arrived for `debounce`
+    pub fn wait(&mut self, debounce: Duration) -> io::Result<()> {
+        while !self.read_events()? {}
+        while self.poll(debounce)? {
+            self.read_events()?;
+        }
+        Ok(())
+    }
+
+    fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } {
+            -1 => Err(io::Error::last_os_error()),
+            ready => Ok(ready > 0),
+        }
+    }
+
+    /// reads the pending events, returns whether one of them concerns a watched file
+    fn read_events(&mut self) -> io::Result<bool> {
+        let mut buffer = [0u8; 4096];
+        let len = unsafe { libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+        if len < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(error),
+            };
+        }
+        let events = &buffer[..len as usize];
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + header <= events.len() {
+            let field = |at: usize| -> [u8; 4] { events[offset + at..offset + at + 4].try_into().unwrap() };
+            let wd = libc::c_int::from_ne_bytes(field(0));
+            let mask = u32::from_ne_bytes(field(4));
+            let name_len = u32::from_ne_bytes(field(12)) as usize;
+            let name = &events[offset + header..offset + header + name_len];
+            // the name is padded with nul bytes
+            let name = OsStr::from_bytes(&name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())]);
+            if mask & libc::IN_Q_OVERFLOW != 0 || self.files.contains(&(wd, name.into())) {
+                changed = true;
+            }
+            offset += header + name_len;
+        }
+        Ok(changed)
+    }
+}
+
+/// the nearest existing directory above `file` and the name of the entry in it leading to `file`,
+/// so that creating a missing directory counts as a change
+fn existing_parent(file: &Path) -> Option<(&Path, &OsStr)> {
+    let mut name = file.file_name()?;
+    let mut dir = file.parent()?;
+    while !dir.as_os_str().is_empty() && !dir.is_dir() {
+        name = dir.file_name()?;
+        dir = dir.parent()?;
+    }
+    if dir.as_os_str().is_empty() {
+        dir = Path::new(".");
+    }
+    Some((dir, name))
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::Path;
+
+    use super::{existing_parent, Watcher};
+
+    #[test]
+    fn missing_directories_are_watched_through_their_parent() {
+        let dir = std::env::temp_dir().join(format!("kay-test-{}-watch", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("missing/deeper/p.tmpl");
+        assert_eq!(existing_parent(&file), Some((dir.as_path(), OsStr::new("missing"))));
+        assert_eq!(existing_parent(Path::new("p.tmpl")), Some((Path::new("."), OsStr::new("p.tmpl"))));
+        assert_eq!(existing_parent(Path::new("nodir/p.tmpl")), Some((Path::new("."), OsStr::new("nodir"))));
+        let mut watcher = Watcher::new().unwrap();
+        assert!(watcher.watch(&[&file, &dir.join("p.tmpl")]).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}